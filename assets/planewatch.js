@@ -54,6 +54,9 @@ map.on('move', () => {
 
 const wsProto = window.location.protocol === 'https:' ? 'wss:' : 'ws';
 
+// current position of each aircraft, until it moves or expires
+const markers = new Map();
+
 const ws = new WebSocket(`${wsProto}//${window.location.host}/ws`);
 ws.addEventListener('message', (event) => {
     function markerElement(isNew, mode_s) {
@@ -70,7 +73,32 @@ ws.addEventListener('message', (event) => {
         return pointsMarker;
     }
 
-    const [mode_s, point] = JSON.parse(event.data);
+    const data = JSON.parse(event.data);
+
+    // sent once on connect, centers the map on the receiver if its location is configured
+    if (data.station) {
+        const {lat, long} = data.station;
+        if (lat !== null && long !== null) {
+            map.setCenter([long, lat]);
+        }
+        return;
+    }
+
+    // aircraft that weren't seen for too long are taken off the map
+    if (data.expired) {
+        for (const mode_s of data.expired) {
+            markers.get(mode_s)?.remove();
+            markers.delete(mode_s);
+        }
+        return;
+    }
+
+    // other notices aren't drawn
+    if (!Array.isArray(data)) {
+        return;
+    }
+
+    const [mode_s, point] = data;
 
     if (isNaN(point[0]) || isNaN(point[1])) {
         return;
@@ -79,6 +107,8 @@ ws.addEventListener('message', (event) => {
     pointsHistory.push([mode_s, point]);
     placePoint(mode_s, point, map, pointsOverlay, pointsOverlayCtx);
 
+    markers.get(mode_s)?.remove();
+
     const pointsMarker = markerElement(true);
     const marker = new mapboxgl.Marker(pointsMarker);
     marker.setLngLat({
@@ -86,7 +116,5 @@ ws.addEventListener('message', (event) => {
         lat: point[0],
     }).addTo(map);
 
-    setTimeout(() => {
-        marker.remove();
-    }, 1000);
+    markers.set(mode_s, marker);
 });
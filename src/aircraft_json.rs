@@ -0,0 +1,138 @@
+//! `aircraft.json` published over HTTP by readsb and dump1090, with already decoded state:
+//! `{"now": 1700000000.0, "aircraft": [{"hex": "4ca2d1", "lat": 41.7, "lon": 44.8, ...}]}`
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+use smol_str::SmolStr;
+
+use crate::{
+    point::{normalize_mode_s, Report},
+    sbs::is_squawk,
+};
+
+/// Decodes reports from the file. Position is only included if it was received after the
+/// one in the previous poll, as the file keeps listing the last known position for a while.
+/// `positions_at` holds when each aircraft's last reported position was received
+pub fn parse(body: &[u8], positions_at: &mut HashMap<SmolStr, f64>) -> Result<Vec<Report>, String> {
+    let file: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let now = file
+        .get("now")
+        .and_then(Value::as_f64)
+        .ok_or("no \"now\" timestamp")?;
+    let aircraft = file
+        .get("aircraft")
+        .and_then(Value::as_array)
+        .ok_or("no \"aircraft\" array")?;
+
+    let mut reports = Vec::with_capacity(aircraft.len());
+    let mut listed = HashMap::with_capacity(aircraft.len());
+
+    for entry in aircraft.iter().filter_map(Value::as_object) {
+        let Some(mode_s) = entry
+            .get("hex")
+            .and_then(Value::as_str)
+            .and_then(normalize_mode_s)
+        else {
+            continue;
+        };
+
+        let position = number(entry, &["lat"])
+            .zip(number(entry, &["lon"]))
+            .map(|(lat, long)| (lat as f32, long as f32));
+        let position_at = now - number(entry, &["seen_pos"]).unwrap_or(0.0);
+
+        let previous = positions_at.get(&mode_s).copied();
+        let position = position.filter(|_| previous.is_none_or(|previous| position_at > previous));
+
+        let latest = if position.is_some() {
+            Some(position_at)
+        } else {
+            previous
+        };
+        if let Some(latest) = latest {
+            listed.insert(mode_s.clone(), latest);
+        }
+
+        // readsb reports "ground" instead of a barometric altitude for aircraft on the ground
+        let on_ground = match entry.get("alt_baro") {
+            Some(Value::String(altitude)) => Some(altitude == "ground"),
+            Some(Value::Number(_)) => Some(false),
+            _ => None,
+        };
+
+        reports.push(Report {
+            mode_s,
+            position,
+            altitude: number(entry, &["alt_baro", "altitude"]).map(|altitude| altitude as i32),
+            callsign: entry
+                .get("flight")
+                .and_then(Value::as_str)
+                .map(str::trim_end)
+                .filter(|callsign| !callsign.is_empty())
+                .map(SmolStr::new),
+            ground_speed: number(entry, &["gs", "speed"]).map(|speed| speed as f32),
+            track: number(entry, &["track"]).map(|track| track as f32),
+            vertical_rate: number(entry, &["baro_rate", "geom_rate", "vert_rate"])
+                .map(|rate| rate as i32),
+            squawk: entry
+                .get("squawk")
+                .and_then(Value::as_str)
+                .filter(|squawk| is_squawk(squawk))
+                .map(SmolStr::new),
+            on_ground,
+            ident: number(entry, &["spi"]).map(|spi| spi != 0.0),
+            rssi: number(entry, &["rssi"]).map(|rssi| rssi as f32),
+        });
+    }
+
+    // aircraft that dropped out of the file are forgotten
+    *positions_at = listed;
+
+    Ok(reports)
+}
+
+/// First of the given fields that holds a number, as field names differ between versions
+fn number(entry: &Map<String, Value>, names: &[&str]) -> Option<f64> {
+    names
+        .iter()
+        .find_map(|name| entry.get(*name).and_then(Value::as_f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn squawks(entries: &str) -> Vec<Option<SmolStr>> {
+        let body = format!(r#"{{"now": 1700000000.0, "aircraft": [{entries}]}}"#);
+
+        parse(body.as_bytes(), &mut HashMap::new())
+            .unwrap()
+            .into_iter()
+            .map(|report| report.squawk)
+            .collect()
+    }
+
+    #[test]
+    fn valid_squawks() {
+        assert_eq!(
+            squawks(
+                r#"{"hex": "4ca2d1", "squawk": "7700"}, {"hex": "4ca2d2", "squawk": "0000"},
+                {"hex": "4ca2d3"}"#
+            ),
+            [Some("7700".into()), Some("0000".into()), None]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_squawks() {
+        assert_eq!(
+            squawks(
+                r#"{"hex": "4ca2d1", "squawk": "7800"}, {"hex": "4ca2d2", "squawk": "770"},
+                {"hex": "4ca2d3", "squawk": "77000"}, {"hex": "4ca2d4", "squawk": ""},
+                {"hex": "4ca2d5", "squawk": "ab12"}, {"hex": "4ca2d6", "squawk": 7700}"#
+            ),
+            [None, None, None, None, None, None]
+        );
+    }
+}
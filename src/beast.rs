@@ -0,0 +1,468 @@
+//! BEAST binary format, as sent by dump1090 and readsb on port 30005,
+//! and decoding of ADS-B (DF17/DF18) messages carried in it
+
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    io::{self, BufReader, Bytes, Read},
+    time::{Duration, Instant},
+};
+
+use smol_str::SmolStr;
+
+use crate::point::Report;
+
+/// Frame start marker, doubled when it occurs inside frame data
+const ESCAPE: u8 = 0x1a;
+
+/// Length of MLAT timestamp and signal level, preceding the message in each frame
+const FRAME_HEADER_LEN: usize = 7;
+const LONG_MESSAGE_LEN: usize = 14;
+
+/// CRC-24 generator polynomial used by Mode S parity
+const CRC_POLY: u32 = 0x1fff409;
+
+/// Max time between even and odd CPR frames for them to be decoded as a pair
+const CPR_PAIR_MAX_AGE: Duration = Duration::from_secs(10);
+
+/// Number of latitude zones between equator and a pole in CPR encoding
+const CPR_NZ: f64 = 15.0;
+/// CPR coordinates are 17-bit fractions
+const CPR_MAX: f64 = 131072.0;
+
+const CALLSIGN_CHARSET: &[u8; 64] =
+    b"#ABCDEFGHIJKLMNOPQRSTUVWXYZ##### ###############0123456789######";
+
+/// Splits BEAST byte stream into frames, unescaping their contents.
+/// Mode A/C and unknown frames are skipped
+pub struct FrameReader<R> {
+    bytes: Bytes<BufReader<R>>,
+    /// Type of the frame that started in the middle of a previous, truncated one
+    pending_type: Option<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            pending_type: None,
+        }
+    }
+
+    /// Reads Mode S message from the next frame, returning `None` at the end of stream
+    pub fn next_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        'frames: loop {
+            let frame_type = match self.pending_type.take() {
+                Some(frame_type) => frame_type,
+                None => {
+                    // skip everything up to the next frame start
+                    loop {
+                        match self.next_byte()? {
+                            Some(ESCAPE) => break,
+                            Some(_) => {}
+                            None => return Ok(None),
+                        }
+                    }
+
+                    match self.next_byte()? {
+                        Some(frame_type) => frame_type,
+                        None => return Ok(None),
+                    }
+                }
+            };
+
+            let message_len = match frame_type {
+                b'1' => 2,
+                b'2' => 7,
+                b'3' => LONG_MESSAGE_LEN,
+                // status frames and escaped data bytes seen while resyncing
+                _ => continue,
+            };
+
+            let mut frame = [0; FRAME_HEADER_LEN + LONG_MESSAGE_LEN];
+
+            for byte in frame.iter_mut().take(FRAME_HEADER_LEN + message_len) {
+                *byte = match self.next_byte()? {
+                    Some(ESCAPE) => match self.next_byte()? {
+                        Some(ESCAPE) => ESCAPE,
+                        // unescaped marker means the frame was truncated and a new one started
+                        Some(frame_type) => {
+                            self.pending_type = Some(frame_type);
+
+                            continue 'frames;
+                        }
+                        None => return Ok(None),
+                    },
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+            }
+
+            // Mode A/C replies carry no position
+            if frame_type == b'1' {
+                continue;
+            }
+
+            return Ok(Some(
+                frame[FRAME_HEADER_LEN..FRAME_HEADER_LEN + message_len].to_vec(),
+            ));
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        self.bytes.next().transpose()
+    }
+}
+
+/// Message parity doesn't match its contents
+#[derive(Debug)]
+pub struct BadCrc;
+
+/// Single CPR-encoded position
+#[derive(Clone, Copy, Debug)]
+struct CprFrame {
+    lat: u32,
+    long: u32,
+    received: Instant,
+}
+
+/// Latest even and odd CPR frames of an aircraft
+#[derive(Debug, Default)]
+struct CprPair {
+    even: Option<CprFrame>,
+    odd: Option<CprFrame>,
+}
+
+/// Decodes ADS-B messages into reports, keeping CPR frames needed to resolve positions
+#[derive(Debug, Default)]
+pub struct Decoder {
+    cpr: HashMap<u32, CprPair>,
+    /// When CPR frames too old to be paired were last dropped
+    pruned_at: Option<Instant>,
+}
+
+impl Decoder {
+    /// Decodes a single Mode S message. Messages other than DF17/DF18 extended squitters
+    /// are ignored, as are extended squitters that carry nothing of interest
+    pub fn decode(&mut self, message: &[u8]) -> Result<Option<Report>, BadCrc> {
+        if message.len() != LONG_MESSAGE_LEN {
+            return Ok(None);
+        }
+
+        let df = message[0] >> 3;
+        if df != 17 && df != 18 {
+            return Ok(None);
+        }
+
+        let (data, parity) = message.split_at(LONG_MESSAGE_LEN - 3);
+        if crc(data) != u32::from_be_bytes([0, parity[0], parity[1], parity[2]]) {
+            return Err(BadCrc);
+        }
+
+        let icao = u32::from_be_bytes([0, message[1], message[2], message[3]]);
+        let me = &message[4..11];
+
+        let mut report = Report {
+            mode_s: SmolStr::new(format!("{icao:06X}")),
+            ..Report::default()
+        };
+
+        match me[0] >> 3 {
+            1..=4 => report.callsign = decode_callsign(me),
+            // surface positions need a reference location to be decoded, so only the fact
+            // that the aircraft is on the ground is used
+            5..=8 => report.on_ground = Some(true),
+            9..=18 => {
+                report.altitude = decode_altitude(me);
+                report.position = self.decode_position(icao, me);
+                report.on_ground = Some(false);
+                // surveillance status 3 is the SPI condition
+                report.ident = Some((me[0] >> 1) & 0b11 == 3);
+            }
+            19 => {
+                if let Some((ground_speed, track)) = decode_velocity(me) {
+                    report.ground_speed = Some(ground_speed);
+                    report.track = Some(track);
+                }
+                report.vertical_rate = decode_vertical_rate(me);
+            }
+            // GNSS altitude isn't comparable to barometric one, so only position is used
+            20..=22 => {
+                report.position = self.decode_position(icao, me);
+                report.on_ground = Some(false);
+            }
+            // emergency/priority status, carrying the Mode A code
+            28 if me[0] & 0x07 == 1 => report.squawk = Some(decode_squawk(me)),
+            _ => return Ok(None),
+        }
+
+        Ok(Some(report))
+    }
+
+    /// Stores the CPR frame and resolves global position if a recent pair is available
+    fn decode_position(&mut self, icao: u32, me: &[u8]) -> Option<(f32, f32)> {
+        let odd = me[2] & 0x04 != 0;
+        let frame = CprFrame {
+            lat: (u32::from(me[2] & 0x03) << 15) | (u32::from(me[3]) << 7) | u32::from(me[4] >> 1),
+            long: (u32::from(me[4] & 0x01) << 16) | (u32::from(me[5]) << 8) | u32::from(me[6]),
+            received: Instant::now(),
+        };
+
+        self.prune(frame.received);
+
+        let pair = self.cpr.entry(icao).or_default();
+        if odd {
+            pair.odd = Some(frame);
+        } else {
+            pair.even = Some(frame);
+        }
+
+        let (even, odd) = (pair.even?, pair.odd?);
+        let age = if even.received > odd.received {
+            even.received - odd.received
+        } else {
+            odd.received - even.received
+        };
+
+        if age > CPR_PAIR_MAX_AGE {
+            return None;
+        }
+
+        decode_cpr(even, odd)
+    }
+
+    /// Drops frames of aircraft that weren't heard from for longer than a pair can span,
+    /// as they can't resolve a position anymore. Only done at most every [`CPR_PAIR_MAX_AGE`]
+    fn prune(&mut self, now: Instant) {
+        if self
+            .pruned_at
+            .is_some_and(|pruned_at| now.duration_since(pruned_at) < CPR_PAIR_MAX_AGE)
+        {
+            return;
+        }
+
+        self.cpr.retain(|_, pair| {
+            [pair.even, pair.odd]
+                .into_iter()
+                .flatten()
+                .any(|frame| now.duration_since(frame.received) <= CPR_PAIR_MAX_AGE)
+        });
+        self.pruned_at = Some(now);
+    }
+}
+
+/// Global CPR decoding of an even/odd frame pair, see "The 1090MHz Riddle" for details
+fn decode_cpr(even: CprFrame, odd: CprFrame) -> Option<(f32, f32)> {
+    let lat_even_cpr = f64::from(even.lat) / CPR_MAX;
+    let lat_odd_cpr = f64::from(odd.lat) / CPR_MAX;
+    let long_even_cpr = f64::from(even.long) / CPR_MAX;
+    let long_odd_cpr = f64::from(odd.long) / CPR_MAX;
+
+    let j = (59.0 * lat_even_cpr - 60.0 * lat_odd_cpr + 0.5).floor();
+
+    let normalize = |lat: f64| if lat >= 270.0 { lat - 360.0 } else { lat };
+    let lat_even = normalize(360.0 / 60.0 * (j.rem_euclid(60.0) + lat_even_cpr));
+    let lat_odd = normalize(360.0 / 59.0 * (j.rem_euclid(59.0) + lat_odd_cpr));
+
+    // frames from different latitude zones can't be decoded together
+    if cpr_nl(lat_even) != cpr_nl(lat_odd) {
+        return None;
+    }
+
+    let (lat, nl, long_cpr, ni) = if even.received >= odd.received {
+        let nl = cpr_nl(lat_even);
+        (lat_even, nl, long_even_cpr, nl.max(1.0))
+    } else {
+        let nl = cpr_nl(lat_odd);
+        (lat_odd, nl, long_odd_cpr, (nl - 1.0).max(1.0))
+    };
+
+    let m = (long_even_cpr * (nl - 1.0) - long_odd_cpr * nl + 0.5).floor();
+    let mut long = 360.0 / ni * (m.rem_euclid(ni) + long_cpr);
+    if long >= 180.0 {
+        long -= 360.0;
+    }
+
+    Some((lat as f32, long as f32))
+}
+
+/// Number of longitude zones at given latitude
+fn cpr_nl(lat: f64) -> f64 {
+    let lat = lat.abs();
+
+    if lat == 0.0 {
+        59.0
+    } else if lat == 87.0 {
+        2.0
+    } else if lat > 87.0 {
+        1.0
+    } else {
+        let a = 1.0 - (PI / (2.0 * CPR_NZ)).cos();
+        let b = (PI / 180.0 * lat).cos().powi(2);
+
+        (2.0 * PI / (1.0 - a / b).acos()).floor()
+    }
+}
+
+/// Barometric altitude in feet, only 25ft increments (Q bit set) are supported
+fn decode_altitude(me: &[u8]) -> Option<i32> {
+    let raw = (u16::from(me[1]) << 4) | u16::from(me[2] >> 4);
+
+    if raw & 0x10 == 0 {
+        return None;
+    }
+
+    let n = ((raw & 0xfe0) >> 1) | (raw & 0x0f);
+
+    Some(i32::from(n) * 25 - 1000)
+}
+
+/// Mode A code from the 13-bit identity field, with its interleaved
+/// `C1 A1 C2 A2 C4 A4 X B1 D1 B2 D2 B4 D4` bits regrouped into 4 octal digits
+fn decode_squawk(me: &[u8]) -> SmolStr {
+    let id = (u16::from(me[1] & 0x1f) << 8) | u16::from(me[2]);
+    let bit = |n: u16| (id >> n) & 1;
+
+    let a = bit(7) << 2 | bit(9) << 1 | bit(11);
+    let b = bit(1) << 2 | bit(3) << 1 | bit(5);
+    let c = bit(8) << 2 | bit(10) << 1 | bit(12);
+    let d = bit(0) << 2 | bit(2) << 1 | bit(4);
+
+    SmolStr::new(format!("{a}{b}{c}{d}"))
+}
+
+/// 8 six-bit characters, padding is stripped
+fn decode_callsign(me: &[u8]) -> Option<SmolStr> {
+    let bits = me[1..7]
+        .iter()
+        .fold(0u64, |bits, &byte| (bits << 8) | u64::from(byte));
+
+    let callsign: String = (0..8)
+        .rev()
+        .map(|i| CALLSIGN_CHARSET[((bits >> (i * 6)) & 0x3f) as usize] as char)
+        .filter(|&c| c != '#')
+        .collect();
+    let callsign = callsign.trim();
+
+    (!callsign.is_empty()).then(|| SmolStr::new(callsign))
+}
+
+/// Ground speed in knots and track in degrees from ground velocity messages (subtypes 1 and 2)
+fn decode_velocity(me: &[u8]) -> Option<(f32, f32)> {
+    let subtype = me[0] & 0x07;
+    let multiplier = match subtype {
+        1 => 1.0,
+        // supersonic
+        2 => 4.0,
+        _ => return None,
+    };
+
+    let ew_raw = (u16::from(me[1] & 0x03) << 8) | u16::from(me[2]);
+    let ns_raw = (u16::from(me[3] & 0x7f) << 3) | u16::from(me[4] >> 5);
+
+    // zero means "no information"
+    if ew_raw == 0 || ns_raw == 0 {
+        return None;
+    }
+
+    let mut east = f64::from(ew_raw - 1) * multiplier;
+    if me[1] & 0x04 != 0 {
+        east = -east;
+    }
+
+    let mut north = f64::from(ns_raw - 1) * multiplier;
+    if me[3] & 0x80 != 0 {
+        north = -north;
+    }
+
+    let speed = east.hypot(north);
+    let track = east.atan2(north).to_degrees().rem_euclid(360.0);
+
+    Some((speed as f32, track as f32))
+}
+
+/// Vertical rate in feet per minute from airborne velocity messages, negative when descending
+fn decode_vertical_rate(me: &[u8]) -> Option<i32> {
+    let raw = (u16::from(me[4] & 0x07) << 6) | u16::from(me[5] >> 2);
+
+    // zero means "no information"
+    if raw == 0 {
+        return None;
+    }
+
+    let rate = i32::from(raw - 1) * 64;
+
+    Some(if me[4] & 0x08 != 0 { -rate } else { rate })
+}
+
+/// Mode S CRC-24 of message data
+fn crc(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+
+        for _ in 0..8 {
+            crc <<= 1;
+
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC_POLY;
+            }
+        }
+    }
+
+    crc & 0xffffff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(received: Instant) -> Option<CprFrame> {
+        Some(CprFrame {
+            lat: 0,
+            long: 0,
+            received,
+        })
+    }
+
+    #[test]
+    fn prunes_frames_too_old_to_pair() {
+        let start = Instant::now();
+        let mut decoder = Decoder::default();
+        decoder.cpr.insert(
+            1,
+            CprPair {
+                even: frame(start),
+                odd: None,
+            },
+        );
+        decoder.cpr.insert(
+            2,
+            CprPair {
+                even: frame(start),
+                odd: frame(start + Duration::from_secs(5)),
+            },
+        );
+
+        decoder.prune(start + CPR_PAIR_MAX_AGE);
+        assert_eq!(decoder.cpr.len(), 2);
+
+        // pruning isn't done more often than the max age
+        decoder.prune(start + CPR_PAIR_MAX_AGE + Duration::from_secs(6));
+        assert_eq!(decoder.cpr.len(), 2);
+
+        decoder.prune(start + CPR_PAIR_MAX_AGE * 2);
+        assert!(decoder.cpr.is_empty());
+
+        decoder.cpr.insert(
+            3,
+            CprPair {
+                even: None,
+                odd: frame(start + CPR_PAIR_MAX_AGE * 2),
+            },
+        );
+        decoder.prune(start + CPR_PAIR_MAX_AGE * 3);
+        assert_eq!(decoder.cpr.len(), 1);
+    }
+}
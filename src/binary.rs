@@ -0,0 +1,165 @@
+//! Compact binary encoding of live updates for WebSocket clients that opt into it,
+//! either with `?format=binary` or by negotiating the `planewatch.binary` subprotocol.
+//!
+//! Each update is sent as a single binary message. All numbers are little-endian,
+//! and the first byte is the message kind:
+//!
+//! Position (kind `1`), 14 bytes plus optional fields:
+//!
+//! | size | field                                           |
+//! |------|-------------------------------------------------|
+//! | 1    | kind = `1`                                      |
+//! | 4    | ICAO address as `u32` (hex ident parsed base 16) |
+//! | 4    | latitude, `f32` degrees                         |
+//! | 4    | longitude, `f32` degrees                        |
+//! | 1    | bitmask of optional fields present below        |
+//!
+//! Optional fields follow in this order, each only if its bit is set:
+//!
+//! | bit    | size | field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | `0x01` | 4    | altitude, `i32` feet                                   |
+//! | `0x02` | 4    | ground speed, `f32` knots                              |
+//! | `0x04` | 4    | track, `f32` degrees                                   |
+//! | `0x08` | 4    | vertical rate, `i32` feet per minute                   |
+//! | `0x10` | 2    | squawk, `u16` holding the 4 octal digits (0o7700 etc.) |
+//! | `0x20` | 8    | callsign, ASCII padded with spaces                     |
+//! | `0x40` | —    | on-ground state is known                               |
+//! | `0x80` | —    | aircraft is on the ground                              |
+//!
+//! Expiration (kind `2`): `u16` count, followed by that many `u32` ICAO addresses.
+//!
+//! Emergency (kind `3`): `u32` ICAO address, then `u16` squawk as above.
+//!
+//! Ident (kind `4`): `u32` ICAO address.
+//!
+//! Station info sent at connect, and lag notices for queued delivery, are text messages
+//! same as in the JSON format.
+//!
+//! Positions carry no timestamp, to keep them small; clients that need to tell how stale
+//! a position is should use the JSON or typed format instead.
+//!
+//! Aircraft whose hex ident isn't a 24-bit address (e.g. TIS-B ones marked with `~`)
+//! can't be represented, and are left out.
+
+use crate::point::{Point, Update};
+
+/// WebSocket subprotocol selecting this encoding
+pub const SUBPROTOCOL: &str = "planewatch.binary";
+
+const KIND_POSITION: u8 = 1;
+const KIND_EXPIRED: u8 = 2;
+const KIND_EMERGENCY: u8 = 3;
+const KIND_IDENT: u8 = 4;
+
+const HAS_ALTITUDE: u8 = 0x01;
+const HAS_GROUND_SPEED: u8 = 0x02;
+const HAS_TRACK: u8 = 0x04;
+const HAS_VERTICAL_RATE: u8 = 0x08;
+const HAS_SQUAWK: u8 = 0x10;
+const HAS_CALLSIGN: u8 = 0x20;
+const HAS_ON_GROUND: u8 = 0x40;
+const ON_GROUND: u8 = 0x80;
+
+const CALLSIGN_LEN: usize = 8;
+
+/// Encodes the update, returning `None` if there's nothing representable in it
+pub fn encode(update: &Update) -> Option<Vec<u8>> {
+    match update {
+        Update::Position(point) => encode_position(point),
+        Update::Expired(expired) => {
+            let icaos: Vec<u32> = expired.iter().filter_map(|mode_s| icao(mode_s)).collect();
+            if icaos.is_empty() {
+                return None;
+            }
+
+            let count = u16::try_from(icaos.len()).unwrap_or(u16::MAX);
+            let mut out = Vec::with_capacity(3 + icaos.len() * 4);
+            out.push(KIND_EXPIRED);
+            out.extend(count.to_le_bytes());
+            for icao in icaos.iter().take(count.into()) {
+                out.extend(icao.to_le_bytes());
+            }
+
+            Some(out)
+        }
+        Update::Emergency { mode_s, squawk } => {
+            let mut out = vec![KIND_EMERGENCY];
+            out.extend(icao(mode_s)?.to_le_bytes());
+            out.extend(octal(squawk)?.to_le_bytes());
+
+            Some(out)
+        }
+        Update::Ident(mode_s) => {
+            let mut out = vec![KIND_IDENT];
+            out.extend(icao(mode_s)?.to_le_bytes());
+
+            Some(out)
+        }
+    }
+}
+
+fn encode_position(point: &Point) -> Option<Vec<u8>> {
+    let info = &point.info;
+    let squawk = info.squawk.as_deref().and_then(octal);
+
+    let mut out = Vec::with_capacity(44);
+    out.push(KIND_POSITION);
+    out.extend(icao(&point.mode_s)?.to_le_bytes());
+    out.extend(point.lat.to_le_bytes());
+    out.extend(point.long.to_le_bytes());
+
+    let mut flags = 0;
+    let flags_at = out.len();
+    out.push(flags);
+
+    if let Some(altitude) = info.altitude {
+        flags |= HAS_ALTITUDE;
+        out.extend(altitude.to_le_bytes());
+    }
+    if let Some(ground_speed) = info.ground_speed {
+        flags |= HAS_GROUND_SPEED;
+        out.extend(ground_speed.to_le_bytes());
+    }
+    if let Some(track) = info.track {
+        flags |= HAS_TRACK;
+        out.extend(track.to_le_bytes());
+    }
+    if let Some(vertical_rate) = info.vertical_rate {
+        flags |= HAS_VERTICAL_RATE;
+        out.extend(vertical_rate.to_le_bytes());
+    }
+    if let Some(squawk) = squawk {
+        flags |= HAS_SQUAWK;
+        out.extend(squawk.to_le_bytes());
+    }
+    if !info.callsign.is_empty() && info.callsign.is_ascii() {
+        flags |= HAS_CALLSIGN;
+
+        let mut callsign = [b' '; CALLSIGN_LEN];
+        for (byte, char) in callsign.iter_mut().zip(info.callsign.bytes()) {
+            *byte = char;
+        }
+        out.extend(callsign);
+    }
+    match info.on_ground {
+        Some(true) => flags |= HAS_ON_GROUND | ON_GROUND,
+        Some(false) => flags |= HAS_ON_GROUND,
+        None => {}
+    }
+
+    out[flags_at] = flags;
+
+    Some(out)
+}
+
+/// 24-bit ICAO address from the hex ident
+fn icao(mode_s: &str) -> Option<u32> {
+    u32::from_str_radix(mode_s, 16)
+        .ok()
+        .filter(|&icao| icao <= 0xff_ffff)
+}
+
+fn octal(squawk: &str) -> Option<u16> {
+    u16::from_str_radix(squawk, 8).ok()
+}
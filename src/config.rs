@@ -0,0 +1,710 @@
+//! Settings resolved once at startup. Each one is read from a command line flag
+//! (`--flag value` or `--flag=value`), falling back to an environment variable and then
+//! to its default. Unknown flags and malformed values exit the process with a readable message
+
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    process,
+    str::FromStr,
+    time::Duration,
+};
+
+use tracing::info;
+
+use crate::{
+    influx::InfluxConfig,
+    logging,
+    mqtt::MqttConfig,
+    point::AltitudeBands,
+    source::{Protocol, ReplayOptions, Source},
+};
+
+const DEFAULT_BIND: &str = "[::]:12345";
+const DEFAULT_EXPIRE_AFTER_SECS: u64 = 60;
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+const DEFAULT_HISTORY_LIMIT: usize = 40000;
+const DEFAULT_TRACK_LENGTH: usize = 500;
+const DEFAULT_MAX_WS_CONNECTIONS: u64 = 1000;
+const DEFAULT_WS_PING_INTERVAL_SECS: u64 = 30;
+const DEFAULT_WS_SEND_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_WS_QUEUE_CAPACITY: usize = 1024;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_HEALTH_MAX_SILENCE_SECS: u64 = 60;
+const DEFAULT_ALTITUDE_BANDS: &str = "10000,30000";
+const DEFAULT_TOPIC_PREFIX: &str = "planewatch";
+const DEFAULT_INFLUX_BUCKET: &str = "planewatch";
+
+/// Every flag that is looked up, anything else on the command line is rejected
+const FLAGS: &[&str] = &[
+    "--log-filter",
+    "--source",
+    "--protocol",
+    "--poll-interval-ms",
+    "--replay-pace",
+    "--replay-loop",
+    "--bind",
+    "--expire-after",
+    "--lat",
+    "--lon",
+    "--name",
+    "--history-limit",
+    "--history-retention",
+    "--track-length",
+    "--max-ws-connections",
+    "--ws-ping-interval",
+    "--ws-throttle-ms",
+    "--ws-send-timeout",
+    "--ws-queue-capacity",
+    "--request-timeout",
+    "--health-max-silence",
+    "--max-record-rate",
+    "--stats-client-addrs",
+    "--altitude-bands",
+    "--cors-origins",
+    "--aircraft-db",
+    "--mqtt",
+    "--mqtt-topic-prefix",
+    "--mqtt-username",
+    "--mqtt-password",
+    "--influx-url",
+    "--influx-token",
+    "--influx-bucket",
+    "--influx-org",
+    "--state-file",
+    "--record",
+];
+
+pub struct Config {
+    /// `--log-filter` / `RUST_LOG`, `info` by default, see [`logging`]
+    pub log_filter: String,
+    /// `--source` / `PLANEWATCH_SOURCE` in `--protocol` / `PLANEWATCH_PROTOCOL`,
+    /// dump1090's SBS output on localhost by default, see [`Config::source`]
+    pub source: Source,
+    /// `--bind` / `PLANEWATCH_BIND`, `[::]:12345` by default
+    pub bind: SocketAddr,
+    /// `--expire-after` / `PLANEWATCH_EXPIRE_AFTER` in seconds, 60 by default
+    pub expire_after: Duration,
+    /// `--lat` / `PLANEWATCH_LAT` and `--lon` / `PLANEWATCH_LON`, both or neither. Unset by default.
+    /// Must be within ±90 and ±180 degrees
+    pub receiver: Option<(f32, f32)>,
+    /// `--name` / `PLANEWATCH_NAME`, unset by default
+    pub station_name: Option<String>,
+    /// `--history-limit` / `PLANEWATCH_HISTORY_LIMIT`, 40000 points by default. Must be positive
+    pub history_limit: usize,
+    /// `--history-retention` / `PLANEWATCH_HISTORY_RETENTION` in seconds, unlimited by default
+    pub history_retention: Option<Duration>,
+    /// `--track-length` / `PLANEWATCH_TRACK_LENGTH`, 500 points per aircraft by default
+    pub track_length: usize,
+    /// `--max-ws-connections` / `PLANEWATCH_MAX_WS_CONNECTIONS`, 1000 by default
+    pub max_ws_connections: u64,
+    /// `--ws-ping-interval` / `PLANEWATCH_WS_PING_INTERVAL` in seconds, 30 by default.
+    /// Zero disables pings
+    pub ws_ping_interval: Duration,
+    /// `--ws-throttle-ms` / `PLANEWATCH_WS_THROTTLE_MS`, 0 (no throttling) by default
+    pub ws_throttle: Duration,
+    /// `--ws-send-timeout` / `PLANEWATCH_WS_SEND_TIMEOUT` in seconds, 10 by default.
+    /// Zero disables the timeout
+    pub ws_send_timeout: Duration,
+    /// `--ws-queue-capacity` / `PLANEWATCH_WS_QUEUE_CAPACITY`, 1024 updates by default.
+    /// Must be positive. Also bounds how far the InfluxDB and MQTT exporters can fall behind
+    pub ws_queue_capacity: usize,
+    /// `--request-timeout` / `PLANEWATCH_REQUEST_TIMEOUT` in seconds, 30 by default.
+    /// Must be positive
+    pub request_timeout: Duration,
+    /// `--health-max-silence` / `PLANEWATCH_HEALTH_MAX_SILENCE` in seconds, 60 by default
+    pub health_max_silence: Duration,
+    /// `--max-record-rate` / `PLANEWATCH_MAX_RECORD_RATE` per second, unlimited by default.
+    /// Zero means unlimited as well
+    pub max_record_rate: Option<u32>,
+    /// `--stats-client-addrs` / `PLANEWATCH_STATS_CLIENT_ADDRS`, `false` by default,
+    /// so that `/stats` doesn't reveal who is watching
+    pub stats_client_addrs: bool,
+    /// `--altitude-bands` / `PLANEWATCH_ALTITUDE_BANDS`, `10000,30000` feet by default
+    pub altitude_bands: AltitudeBands,
+    /// `--cors-origins` / `PLANEWATCH_CORS_ORIGINS`, localhost only by default, see [`crate::cors`]
+    pub cors_origins: Option<String>,
+    /// `--aircraft-db` / `PLANEWATCH_AIRCRAFT_DB`, unset by default
+    pub aircraft_db: Option<PathBuf>,
+    /// `--mqtt` / `PLANEWATCH_MQTT` with the `--mqtt-*` / `PLANEWATCH_MQTT_*` options,
+    /// disabled by default. A password needs a username
+    pub mqtt: Option<MqttConfig>,
+    /// `--influx-url` / `PLANEWATCH_INFLUX_URL` with the `--influx-*` / `PLANEWATCH_INFLUX_*`
+    /// options, disabled by default
+    pub influx: Option<InfluxConfig>,
+    /// `--state-file` / `PLANEWATCH_STATE_FILE`, unset by default
+    pub state_file: Option<PathBuf>,
+    /// `--record` / `PLANEWATCH_RECORD`, unset by default
+    pub record: Option<PathBuf>,
+}
+
+impl Config {
+    /// Reads all settings from the command line and the environment,
+    /// exiting the process with a readable message if any of them is invalid
+    pub fn load() -> Self {
+        // variables that aren't valid UTF-8 can't hold any valid setting anyway
+        let env = env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        });
+
+        Self::from_sources(env::args().skip(1), env).unwrap_or_else(|e| exit(&e))
+    }
+
+    /// Reads all settings from the given command line arguments (without the program name)
+    /// and environment variables
+    pub fn from_sources(
+        args: impl IntoIterator<Item = String>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let sources = Sources::new(args, env)?;
+
+        let receiver = match (
+            sources.optional("--lat", "PLANEWATCH_LAT")?,
+            sources.optional("--lon", "PLANEWATCH_LON")?,
+        ) {
+            (Some(lat), Some(long)) => Some((lat, long)),
+            (None, None) => None,
+            _ => return Err(
+                "Receiver location needs both --lat / PLANEWATCH_LAT and --lon / PLANEWATCH_LON"
+                    .to_owned(),
+            ),
+        };
+        if let Some((lat, long)) = receiver {
+            if !(-90.0..=90.0).contains(&lat) {
+                return Err(format!(
+                    "--lat / PLANEWATCH_LAT must be between -90 and 90, got {lat}"
+                ));
+            }
+            if !(-180.0..=180.0).contains(&long) {
+                return Err(format!(
+                    "--lon / PLANEWATCH_LON must be between -180 and 180, got {long}"
+                ));
+            }
+        }
+
+        let history_limit = sources.parsed(
+            "--history-limit",
+            "PLANEWATCH_HISTORY_LIMIT",
+            &DEFAULT_HISTORY_LIMIT.to_string(),
+        )?;
+        if history_limit == 0 {
+            return Err("--history-limit / PLANEWATCH_HISTORY_LIMIT must be positive".to_owned());
+        }
+
+        let request_timeout = Duration::from_secs(sources.parsed(
+            "--request-timeout",
+            "PLANEWATCH_REQUEST_TIMEOUT",
+            &DEFAULT_REQUEST_TIMEOUT_SECS.to_string(),
+        )?);
+        // unlike WebSocket sends, requests can't go without a timeout, so zero would fail them all
+        if request_timeout.is_zero() {
+            return Err(
+                "--request-timeout / PLANEWATCH_REQUEST_TIMEOUT must be positive".to_owned(),
+            );
+        }
+
+        let ws_queue_capacity = sources.parsed(
+            "--ws-queue-capacity",
+            "PLANEWATCH_WS_QUEUE_CAPACITY",
+            &DEFAULT_WS_QUEUE_CAPACITY.to_string(),
+        )?;
+        if ws_queue_capacity == 0 {
+            return Err(
+                "--ws-queue-capacity / PLANEWATCH_WS_QUEUE_CAPACITY must be positive".to_owned(),
+            );
+        }
+
+        let mqtt = sources
+            .setting("--mqtt", "PLANEWATCH_MQTT")
+            .map(|url| MqttConfig {
+                broker: MqttConfig::broker_address(&url),
+                topic_prefix: sources
+                    .setting("--mqtt-topic-prefix", "PLANEWATCH_MQTT_TOPIC_PREFIX")
+                    .unwrap_or_else(|| DEFAULT_TOPIC_PREFIX.to_owned()),
+                username: sources.setting("--mqtt-username", "PLANEWATCH_MQTT_USERNAME"),
+                password: sources.setting("--mqtt-password", "PLANEWATCH_MQTT_PASSWORD"),
+            });
+
+        // MQTT 3.1.1 only allows a password along with a username
+        if mqtt
+            .as_ref()
+            .is_some_and(|mqtt| mqtt.password.is_some() && mqtt.username.is_none())
+        {
+            return Err("--mqtt-password / PLANEWATCH_MQTT_PASSWORD needs \
+                        --mqtt-username / PLANEWATCH_MQTT_USERNAME"
+                .to_owned());
+        }
+
+        Ok(Self {
+            log_filter: sources
+                .setting("--log-filter", "RUST_LOG")
+                .unwrap_or_else(|| logging::DEFAULT_FILTER.to_owned()),
+            source: Self::source(&sources)?,
+            bind: sources.parsed("--bind", "PLANEWATCH_BIND", DEFAULT_BIND)?,
+            expire_after: Duration::from_secs(sources.parsed(
+                "--expire-after",
+                "PLANEWATCH_EXPIRE_AFTER",
+                &DEFAULT_EXPIRE_AFTER_SECS.to_string(),
+            )?),
+            receiver,
+            station_name: sources.setting("--name", "PLANEWATCH_NAME"),
+            history_limit,
+            history_retention: sources
+                .optional("--history-retention", "PLANEWATCH_HISTORY_RETENTION")?
+                .map(Duration::from_secs),
+            track_length: sources.parsed(
+                "--track-length",
+                "PLANEWATCH_TRACK_LENGTH",
+                &DEFAULT_TRACK_LENGTH.to_string(),
+            )?,
+            max_ws_connections: sources.parsed(
+                "--max-ws-connections",
+                "PLANEWATCH_MAX_WS_CONNECTIONS",
+                &DEFAULT_MAX_WS_CONNECTIONS.to_string(),
+            )?,
+            ws_ping_interval: Duration::from_secs(sources.parsed(
+                "--ws-ping-interval",
+                "PLANEWATCH_WS_PING_INTERVAL",
+                &DEFAULT_WS_PING_INTERVAL_SECS.to_string(),
+            )?),
+            ws_throttle: Duration::from_millis(sources.parsed(
+                "--ws-throttle-ms",
+                "PLANEWATCH_WS_THROTTLE_MS",
+                "0",
+            )?),
+            ws_send_timeout: Duration::from_secs(sources.parsed(
+                "--ws-send-timeout",
+                "PLANEWATCH_WS_SEND_TIMEOUT",
+                &DEFAULT_WS_SEND_TIMEOUT_SECS.to_string(),
+            )?),
+            ws_queue_capacity,
+            request_timeout,
+            health_max_silence: Duration::from_secs(sources.parsed(
+                "--health-max-silence",
+                "PLANEWATCH_HEALTH_MAX_SILENCE",
+                &DEFAULT_HEALTH_MAX_SILENCE_SECS.to_string(),
+            )?),
+            max_record_rate: sources
+                .optional("--max-record-rate", "PLANEWATCH_MAX_RECORD_RATE")?
+                .filter(|&rate| rate > 0),
+            stats_client_addrs: sources.parsed(
+                "--stats-client-addrs",
+                "PLANEWATCH_STATS_CLIENT_ADDRS",
+                "false",
+            )?,
+            altitude_bands: sources.parsed(
+                "--altitude-bands",
+                "PLANEWATCH_ALTITUDE_BANDS",
+                DEFAULT_ALTITUDE_BANDS,
+            )?,
+            cors_origins: sources.setting("--cors-origins", "PLANEWATCH_CORS_ORIGINS"),
+            aircraft_db: sources
+                .setting("--aircraft-db", "PLANEWATCH_AIRCRAFT_DB")
+                .map(PathBuf::from),
+            mqtt,
+            influx: sources
+                .setting("--influx-url", "PLANEWATCH_INFLUX_URL")
+                .map(|url| InfluxConfig {
+                    url,
+                    token: sources.setting("--influx-token", "PLANEWATCH_INFLUX_TOKEN"),
+                    bucket: sources
+                        .setting("--influx-bucket", "PLANEWATCH_INFLUX_BUCKET")
+                        .unwrap_or_else(|| DEFAULT_INFLUX_BUCKET.to_owned()),
+                    org: sources.setting("--influx-org", "PLANEWATCH_INFLUX_ORG"),
+                }),
+            state_file: sources
+                .setting("--state-file", "PLANEWATCH_STATE_FILE")
+                .map(PathBuf::from),
+            record: sources
+                .setting("--record", "PLANEWATCH_RECORD")
+                .map(PathBuf::from),
+        })
+    }
+
+    /// Source from `--source`: `-` for stdin, `unix://path`, `http://` URL of `aircraft.json`,
+    /// `file://path` of a recording, or a TCP address with an optional `tcp://` prefix
+    fn source(sources: &Sources) -> Result<Source, String> {
+        let protocol: Protocol = sources.parsed("--protocol", "PLANEWATCH_PROTOCOL", "sbs")?;
+        let source = sources
+            .setting("--source", "PLANEWATCH_SOURCE")
+            .unwrap_or_else(|| protocol.default_source().to_owned());
+
+        let source = if source == "-" {
+            Source::Stdin(protocol)
+        } else if let Some(path) = source.strip_prefix("unix://") {
+            Source::Unix(PathBuf::from(path), protocol)
+        } else if source.starts_with("http://") {
+            match source.parse() {
+                Ok(url) => Source::AircraftJson(
+                    url,
+                    Duration::from_millis(sources.parsed(
+                        "--poll-interval-ms",
+                        "PLANEWATCH_POLL_INTERVAL_MS",
+                        &DEFAULT_POLL_INTERVAL_MS.to_string(),
+                    )?),
+                ),
+                Err(e) => return Err(format!("Invalid source URL {source}: {e}")),
+            }
+        } else if let Some(path) = source.strip_prefix("file://") {
+            Source::Replay(
+                PathBuf::from(path),
+                ReplayOptions {
+                    pace: sources.parsed("--replay-pace", "PLANEWATCH_REPLAY_PACE", "true")?,
+                    looped: sources.parsed("--replay-loop", "PLANEWATCH_REPLAY_LOOP", "false")?,
+                },
+            )
+        } else {
+            let source = source.strip_prefix("tcp://").unwrap_or(&source);
+            match source.to_socket_addrs().map(|mut addrs| addrs.next()) {
+                Ok(Some(addr)) => Source::Tcp(addr, protocol),
+                Ok(None) => return Err(format!("Source address {source} resolved to nothing")),
+                Err(e) => return Err(format!("Failed to resolve source address {source}: {e}")),
+            }
+        };
+
+        Ok(source)
+    }
+
+    /// Logs the resolved settings, so that typos in variables show up as defaults.
+    /// Credentials are left out
+    pub fn log_summary(&self) {
+        info!(
+            source = %self.source,
+            bind = %self.bind,
+            receiver = ?self.receiver,
+            name = ?self.station_name,
+            expire_after = ?self.expire_after,
+            history_limit = self.history_limit,
+            history_retention = ?self.history_retention,
+            track_length = self.track_length,
+            max_ws_connections = self.max_ws_connections,
+            ws_ping_interval = ?self.ws_ping_interval,
+            ws_throttle = ?self.ws_throttle,
+            ws_send_timeout = ?self.ws_send_timeout,
+            ws_queue_capacity = self.ws_queue_capacity,
+            request_timeout = ?self.request_timeout,
+            health_max_silence = ?self.health_max_silence,
+            max_record_rate = ?self.max_record_rate,
+            stats_client_addrs = self.stats_client_addrs,
+            altitude_bands = ?self.altitude_bands,
+            cors_origins = ?self.cors_origins,
+            aircraft_db = ?self.aircraft_db,
+            mqtt = ?self.mqtt.as_ref().map(|mqtt| &mqtt.broker),
+            influx = ?self.influx.as_ref().map(|influx| &influx.url),
+            state_file = ?self.state_file,
+            record = ?self.record,
+            "Resolved configuration"
+        );
+    }
+}
+
+/// Command line flags and environment variables that settings are read from
+struct Sources {
+    /// Values of flags given on the command line. The first one wins if a flag is repeated
+    flags: HashMap<String, String>,
+    env: HashMap<String, String>,
+}
+
+impl Sources {
+    /// Collects flags given either as `--flag value` or `--flag=value`,
+    /// rejecting unknown ones, flags without a value and stray arguments
+    fn new(
+        args: impl IntoIterator<Item = String>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut flags = HashMap::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+
+            if !flag.starts_with("--") {
+                return Err(format!("Unexpected argument {flag:?}"));
+            }
+            if !FLAGS.contains(&flag.as_str()) {
+                return Err(format!("Unknown flag {flag}"));
+            }
+
+            let value = match value {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {flag}"))?,
+            };
+            flags.entry(flag).or_insert(value);
+        }
+
+        Ok(Self {
+            flags,
+            env: env.into_iter().collect(),
+        })
+    }
+
+    /// Looks up a setting in command line flags, falling back to the given environment variable
+    fn setting(&self, flag: &str, env_var: &str) -> Option<String> {
+        debug_assert!(FLAGS.contains(&flag), "{flag} is missing from FLAGS");
+
+        self.flags
+            .get(flag)
+            .or_else(|| self.env.get(env_var))
+            .cloned()
+    }
+
+    /// Looks up a setting like [`Sources::setting`] and parses it, using the default if it's absent
+    fn parsed<T>(&self, flag: &str, env_var: &str, default: &str) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self
+            .setting(flag, env_var)
+            .unwrap_or_else(|| default.to_owned());
+
+        parse(&value, flag, env_var)
+    }
+
+    /// Looks up an optional setting like [`Sources::setting`] and parses it
+    fn optional<T>(&self, flag: &str, env_var: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.setting(flag, env_var)
+            .map(|value| parse(&value, flag, env_var))
+            .transpose()
+    }
+}
+
+fn parse<T>(value: &str, flag: &str, env_var: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value {value:?} for {flag} / {env_var}: {e}"))
+}
+
+/// Settings are read before logging is set up, so errors go straight to stderr
+fn exit(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Config, String> {
+        Config::from_sources(
+            args.iter().map(|arg| arg.to_string()),
+            env.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+    }
+
+    fn error(args: &[&str], env: &[(&str, &str)]) -> String {
+        match load(args, env) {
+            Ok(_) => panic!("{args:?} {env:?} should be rejected"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = load(&[], &[]).unwrap();
+
+        assert_eq!(config.bind, DEFAULT_BIND.parse().unwrap());
+        assert_eq!(config.history_limit, DEFAULT_HISTORY_LIMIT);
+        assert_eq!(config.expire_after, Duration::from_secs(60));
+        assert_eq!(config.receiver, None);
+        assert_eq!(config.max_record_rate, None);
+        assert!(!config.stats_client_addrs);
+        assert!(config.mqtt.is_none());
+        assert!(config.influx.is_none());
+        assert!(matches!(
+            config.source,
+            Source::Tcp(addr, Protocol::Sbs) if addr.to_string() == "127.0.0.1:30003"
+        ));
+    }
+
+    #[test]
+    fn flag_forms() {
+        for args in [
+            &["--history-limit", "500"][..],
+            &["--history-limit=500"],
+            &["--history-limit=500", "--history-limit=600"],
+        ] {
+            assert_eq!(load(args, &[]).unwrap().history_limit, 500, "{args:?}");
+        }
+
+        let config = load(&["--name=a=b", "--cors-origins", "--x"], &[]).unwrap();
+        assert_eq!(config.station_name.as_deref(), Some("a=b"));
+        assert_eq!(config.cors_origins.as_deref(), Some("--x"));
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        let env = [
+            ("PLANEWATCH_HISTORY_LIMIT", "100"),
+            ("PLANEWATCH_NAME", "home"),
+        ];
+
+        let config = load(&[], &env).unwrap();
+        assert_eq!(config.history_limit, 100);
+        assert_eq!(config.station_name.as_deref(), Some("home"));
+
+        let config = load(&["--history-limit", "200"], &env).unwrap();
+        assert_eq!(config.history_limit, 200);
+        assert_eq!(config.station_name.as_deref(), Some("home"));
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert_eq!(
+            error(&["--histroy-limit=5"], &[]),
+            "Unknown flag --histroy-limit"
+        );
+        assert_eq!(
+            error(&["--history-limit5"], &[]),
+            "Unknown flag --history-limit5"
+        );
+        assert_eq!(error(&["--lat=1", "--lng=2"], &[]), "Unknown flag --lng");
+        assert_eq!(error(&["-h"], &[]), "Unexpected argument \"-h\"");
+        assert_eq!(error(&["serve"], &[]), "Unexpected argument \"serve\"");
+        assert_eq!(error(&["--name"], &[]), "Missing value for --name");
+    }
+
+    #[test]
+    fn every_flag_is_known() {
+        for flag in FLAGS {
+            assert!(Sources::new([format!("{flag}=1")], []).is_ok(), "{flag}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert_eq!(
+            error(&["--history-limit", "lots"], &[]),
+            "Invalid value \"lots\" for --history-limit / PLANEWATCH_HISTORY_LIMIT: \
+             invalid digit found in string"
+        );
+        assert!(error(&[], &[("PLANEWATCH_EXPIRE_AFTER", "-1")]).contains("--expire-after"));
+        assert!(error(&["--bind=localhost"], &[]).contains("--bind"));
+        assert!(error(&["--stats-client-addrs=yes"], &[]).contains("--stats-client-addrs"));
+        assert!(error(&["--source=http://exa mple"], &[]).starts_with("Invalid source URL"));
+    }
+
+    #[test]
+    fn receiver_location() {
+        let config = load(&["--lat=53.35", "--lon=-6.26"], &[]).unwrap();
+        assert_eq!(config.receiver, Some((53.35, -6.26)));
+
+        for (lat, long) in [("90", "180"), ("-90", "-180"), ("0", "0")] {
+            let config = load(&["--lat", lat, "--lon", long], &[]).unwrap();
+            assert!(config.receiver.is_some(), "{lat} {long}");
+        }
+
+        assert!(error(&["--lat=53.35"], &[]).starts_with("Receiver location needs both"));
+        assert!(error(&[], &[("PLANEWATCH_LON", "1")]).starts_with("Receiver location needs both"));
+    }
+
+    #[test]
+    fn rejects_receiver_out_of_range() {
+        for lat in ["90.5", "-91", "NaN", "inf"] {
+            assert!(
+                error(&["--lat", lat, "--lon=0"], &[]).starts_with("--lat / PLANEWATCH_LAT"),
+                "{lat}"
+            );
+        }
+        for long in ["180.5", "-360", "NaN", "-inf"] {
+            assert!(
+                error(&["--lat=0", "--lon", long], &[]).starts_with("--lon / PLANEWATCH_LON"),
+                "{long}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        assert_eq!(
+            error(&["--history-limit=0"], &[]),
+            "--history-limit / PLANEWATCH_HISTORY_LIMIT must be positive"
+        );
+        assert_eq!(
+            error(&[], &[("PLANEWATCH_WS_QUEUE_CAPACITY", "0")]),
+            "--ws-queue-capacity / PLANEWATCH_WS_QUEUE_CAPACITY must be positive"
+        );
+        assert_eq!(
+            error(&["--request-timeout", "0"], &[]),
+            "--request-timeout / PLANEWATCH_REQUEST_TIMEOUT must be positive"
+        );
+        assert_eq!(
+            load(&["--request-timeout=1"], &[]).unwrap().request_timeout,
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn sources() {
+        let source = |value: &str| load(&["--source", value], &[]).unwrap().source;
+
+        assert!(matches!(source("-"), Source::Stdin(Protocol::Sbs)));
+        assert!(matches!(
+            source("unix:///run/readsb.sock"),
+            Source::Unix(path, Protocol::Sbs) if path == Path::new("/run/readsb.sock")
+        ));
+        assert!(matches!(
+            source("tcp://10.0.0.2:30003"),
+            Source::Tcp(addr, Protocol::Sbs) if addr.to_string() == "10.0.0.2:30003"
+        ));
+        assert!(matches!(
+            source("http://10.0.0.2/data/aircraft.json"),
+            Source::AircraftJson(_, interval) if interval == Duration::from_secs(1)
+        ));
+        assert!(matches!(
+            source("file://flights.csv"),
+            Source::Replay(path, ReplayOptions { pace: true, looped: false })
+                if path == Path::new("flights.csv")
+        ));
+
+        let config = load(&["--protocol=beast"], &[]).unwrap();
+        assert!(matches!(
+            config.source,
+            Source::Tcp(addr, Protocol::Beast) if addr.port() == 30005
+        ));
+    }
+
+    #[test]
+    fn mqtt_credentials() {
+        let mqtt = |args: &[&str]| load(args, &[]).unwrap().mqtt.unwrap();
+
+        let config = mqtt(&["--mqtt=mqtt://broker/"]);
+        assert_eq!(config.broker, "broker:1883");
+        assert_eq!(config.topic_prefix, DEFAULT_TOPIC_PREFIX);
+        assert_eq!((config.username, config.password), (None, None));
+
+        let config = mqtt(&["--mqtt=broker:8883", "--mqtt-username=pw"]);
+        assert_eq!(config.broker, "broker:8883");
+        assert_eq!(config.username.as_deref(), Some("pw"));
+
+        let config = mqtt(&["--mqtt=broker", "--mqtt-username=pw", "--mqtt-password=x"]);
+        assert_eq!(config.password.as_deref(), Some("x"));
+
+        assert_eq!(
+            error(&["--mqtt=broker", "--mqtt-password=x"], &[]),
+            "--mqtt-password / PLANEWATCH_MQTT_PASSWORD needs \
+             --mqtt-username / PLANEWATCH_MQTT_USERNAME"
+        );
+        // credentials don't matter without a broker
+        assert!(load(&["--mqtt-password=x"], &[]).unwrap().mqtt.is_none());
+    }
+}
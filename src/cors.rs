@@ -0,0 +1,53 @@
+//! Cross-origin access to the API, for frontends served from a different origin
+
+use axum::http::{request::Parts, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// CORS policy for the given comma-separated list of allowed origins
+/// (e.g. `https://map.example.com,http://localhost:8080`), `*` allowing any.
+/// Without a list, only pages served from localhost are allowed.
+///
+/// WebSocket handshakes aren't subject to CORS in browsers, and `/ws` accepts them from any origin
+pub fn layer(origins: Option<&str>) -> Result<CorsLayer, String> {
+    let allow_origin = match origins.map(str::trim) {
+        None => AllowOrigin::predicate(is_localhost),
+        Some("*") => AllowOrigin::any(),
+        Some(origins) => {
+            let origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(|origin| {
+                    HeaderValue::from_str(origin.trim_end_matches('/'))
+                        .map_err(|_| format!("invalid origin {origin:?}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            AllowOrigin::list(origins)
+        }
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::HEAD]))
+}
+
+/// `http(s)://localhost`, `127.0.0.1` or `[::1]`, on any port
+fn is_localhost(origin: &HeaderValue, _request: &Parts) -> bool {
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    let Some(host) = origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+    else {
+        return false;
+    };
+
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => host,
+    };
+
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
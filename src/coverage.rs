@@ -0,0 +1,104 @@
+//! Maximum reception range of the receiver in each compass sector, to plot its coverage polygon
+
+use std::{
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use smol_str::SmolStr;
+
+use crate::point::{unix_timestamp, Point};
+
+/// Number of equal compass sectors, starting at north and going clockwise
+const SECTORS: usize = 16;
+const SECTOR_WIDTH: f32 = 360.0 / SECTORS as f32;
+
+/// Farthest position seen in a sector
+#[derive(Clone, Debug)]
+struct RangeRecord {
+    mode_s: SmolStr,
+    lat: f32,
+    long: f32,
+    distance: f32,
+    seen_at: SystemTime,
+}
+
+/// Farthest positions seen in each sector since startup
+#[derive(Debug, Default)]
+pub struct Coverage {
+    sectors: Mutex<[Option<RangeRecord>; SECTORS]>,
+}
+
+impl Coverage {
+    /// Updates the record of the point's sector if the point is farther than it.
+    /// Points without receiver-relative position are ignored
+    pub fn record(&self, point: &Point) {
+        let Some(polar) = point.polar else {
+            return;
+        };
+
+        let sector = (polar.bearing / SECTOR_WIDTH) as usize % SECTORS;
+        // a record is replaced in one step, so a poisoned lock still guards consistent data
+        let mut sectors = self.sectors.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if sectors[sector]
+            .as_ref()
+            .is_none_or(|record| polar.distance > record.distance)
+        {
+            sectors[sector] = Some(RangeRecord {
+                mode_s: point.mode_s.clone(),
+                lat: point.lat,
+                long: point.long,
+                distance: polar.distance,
+                seen_at: point.seen_at,
+            });
+        }
+    }
+
+    pub fn snapshot(&self) -> CoverageSnapshot {
+        CoverageSnapshot(
+            self.sectors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        )
+    }
+}
+
+/// Coverage at some moment, serialized as an array of sectors clockwise from north:
+/// `[{"from": 0, "to": 22.5, "distance": ..., "hex": ..., "lat": ..., "long": ..., "seen_at": ...}, ...]`.
+/// Record fields are `null` for sectors where nothing was seen yet
+pub struct CoverageSnapshot([Option<RangeRecord>; SECTORS]);
+
+impl Serialize for CoverageSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(sector, record)| Sector(sector, record.as_ref())),
+        )
+    }
+}
+
+struct Sector<'a>(usize, Option<&'a RangeRecord>);
+
+impl Serialize for Sector<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Sector(sector, record) = *self;
+
+        let mut map = serializer.serialize_map(Some(7))?;
+        map.serialize_entry("from", &(sector as f32 * SECTOR_WIDTH))?;
+        map.serialize_entry("to", &((sector + 1) as f32 * SECTOR_WIDTH))?;
+        map.serialize_entry("distance", &record.map(|record| record.distance))?;
+        map.serialize_entry("hex", &record.map(|record| &record.mode_s))?;
+        map.serialize_entry("lat", &record.map(|record| record.lat))?;
+        map.serialize_entry("long", &record.map(|record| record.long))?;
+        map.serialize_entry(
+            "seen_at",
+            &record.map(|record| unix_timestamp(record.seen_at)),
+        )?;
+        map.end()
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use smol_str::SmolStr;
+
+use crate::point::{normalize_mode_s, Point, Update};
+
+/// Per-connection filter of forwarded updates
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub bbox: BoundingBox,
+    pub altitude: AltitudeRange,
+    /// If set, only this aircraft is forwarded
+    pub mode_s: Option<SmolStr>,
+}
+
+impl Filter {
+    pub fn contains(&self, point: &Point) -> bool {
+        self.bbox.contains(point)
+            && self.altitude.contains(point)
+            && self
+                .mode_s
+                .as_ref()
+                .is_none_or(|mode_s| *mode_s == point.mode_s)
+    }
+
+    /// Whether the update should be forwarded to the client.
+    /// Expirations are always forwarded, as the aircraft could've matched the filter before,
+    /// and emergencies and idents are only checked against the followed aircraft,
+    /// as they carry no position
+    pub fn matches(&self, update: &Update) -> bool {
+        match update {
+            Update::Position(point) => self.contains(point),
+            Update::Expired(_) => true,
+            Update::Emergency { mode_s, .. } | Update::Ident(mode_s) => self
+                .mode_s
+                .as_ref()
+                .is_none_or(|followed| followed == mode_s),
+        }
+    }
+
+    /// Applies a JSON command sent by the client: `{"bbox": [min_lat, min_lon, max_lat, max_lon]}`
+    /// to change the viewport, `{"min_alt": 10000, "max_alt": null, "unknown_alt": false}`
+    /// to change the altitude range (any of the keys), or `{"hex": "ABC123"}` to follow
+    /// a single aircraft. `null` resets the respective part of the filter
+    pub fn apply_command(&mut self, command: &str) -> Result<(), String> {
+        let command: Value = serde_json::from_str(command).map_err(|e| e.to_string())?;
+        let command = command.as_object().ok_or("command must be an object")?;
+
+        let bbox = match command.get("bbox") {
+            None => self.bbox,
+            Some(Value::Null) => BoundingBox::default(),
+            Some(Value::Array(bounds)) => {
+                let bounds: Option<Vec<f32>> = bounds
+                    .iter()
+                    .map(|bound| bound.as_f64().map(|bound| bound as f32))
+                    .collect();
+
+                match bounds.as_deref() {
+                    Some(&[min_lat, min_long, max_lat, max_long]) => BoundingBox {
+                        min_lat: Some(min_lat),
+                        max_lat: Some(max_lat),
+                        min_long: Some(min_long),
+                        max_long: Some(max_long),
+                    },
+                    _ => return Err("bbox must be an array of 4 numbers".to_owned()),
+                }
+            }
+            Some(_) => return Err("bbox must be an array or null".to_owned()),
+        };
+
+        let mode_s = match command.get("hex") {
+            None => self.mode_s.clone(),
+            Some(Value::Null) => None,
+            Some(Value::String(mode_s)) => match normalize_mode_s(mode_s) {
+                Some(mode_s) => Some(mode_s),
+                None => return Err(format!("invalid hex {mode_s:?}")),
+            },
+            Some(_) => return Err("hex must be a string or null".to_owned()),
+        };
+
+        let altitude_bound = |name: &str, current: Option<i32>| match command.get(name) {
+            None => Ok(current),
+            Some(Value::Null) => Ok(None),
+            Some(bound) => bound
+                .as_i64()
+                .and_then(|bound| i32::try_from(bound).ok())
+                .map(Some)
+                .ok_or(format!("{name} must be an integer or null")),
+        };
+        let altitude = AltitudeRange {
+            min: altitude_bound("min_alt", self.altitude.min)?,
+            max: altitude_bound("max_alt", self.altitude.max)?,
+            include_unknown: match command.get("unknown_alt") {
+                None => self.altitude.include_unknown,
+                Some(Value::Null) => false,
+                Some(Value::Bool(include)) => *include,
+                Some(_) => return Err("unknown_alt must be a boolean or null".to_owned()),
+            },
+        };
+
+        const KEYS: [&str; 5] = ["bbox", "hex", "min_alt", "max_alt", "unknown_alt"];
+        if !KEYS.iter().any(|key| command.contains_key(*key)) {
+            return Err("unknown command".to_owned());
+        }
+
+        self.bbox = bbox;
+        self.altitude = altitude;
+        self.mode_s = mode_s;
+
+        Ok(())
+    }
+}
+
+/// Geographic area that a client is interested in. Bounds are inclusive,
+/// and missing ones don't restrict anything
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BoundingBox {
+    pub min_lat: Option<f32>,
+    pub max_lat: Option<f32>,
+    pub min_long: Option<f32>,
+    pub max_long: Option<f32>,
+}
+
+impl BoundingBox {
+    /// Reads bounds from `min_lat`, `max_lat`, `min_lon` and `max_lon` query parameters.
+    /// Bounds must be finite, and are clamped to the valid coordinate range
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Self, String> {
+        let bound = |name: &str, limit: f32| {
+            query
+                .get(name)
+                .map(|value| match value.parse::<f32>() {
+                    Ok(bound) if bound.is_finite() => Ok(bound.clamp(-limit, limit)),
+                    Ok(_) => Err(format!("invalid {name} {value:?}: must be finite")),
+                    Err(e) => Err(format!("invalid {name} {value:?}: {e}")),
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            min_lat: bound("min_lat", 90.0)?,
+            max_lat: bound("max_lat", 90.0)?,
+            min_long: bound("min_lon", 180.0)?,
+            max_long: bound("max_lon", 180.0)?,
+        })
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        self.min_lat.is_none_or(|min| point.lat >= min)
+            && self.max_lat.is_none_or(|max| point.lat <= max)
+            && self.min_long.is_none_or(|min| point.long >= min)
+            && self.max_long.is_none_or(|max| point.long <= max)
+    }
+}
+
+/// Altitude range, in feet, that a client is interested in. Bounds are inclusive,
+/// and missing ones don't restrict anything. Aircraft with unknown altitude only match
+/// if they are asked for, or if no bound is set
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AltitudeRange {
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    pub include_unknown: bool,
+}
+
+impl AltitudeRange {
+    /// Reads bounds from `min_alt` and `max_alt` query parameters,
+    /// and whether to include unknown altitude from `unknown_alt`
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Self, String> {
+        let bound = |name: &str| {
+            query
+                .get(name)
+                .map(|value| {
+                    value
+                        .parse::<i32>()
+                        .map_err(|e| format!("invalid {name} {value:?}: {e}"))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            min: bound("min_alt")?,
+            max: bound("max_alt")?,
+            include_unknown: query
+                .get("unknown_alt")
+                .map(|value| {
+                    value
+                        .parse::<bool>()
+                        .map_err(|e| format!("invalid unknown_alt {value:?}: {e}"))
+                })
+                .transpose()?
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        match point.info.altitude {
+            Some(altitude) => {
+                self.min.is_none_or(|min| altitude >= min)
+                    && self.max.is_none_or(|max| altitude <= max)
+            }
+            None => self.include_unknown || (self.min.is_none() && self.max.is_none()),
+        }
+    }
+}
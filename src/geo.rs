@@ -0,0 +1,46 @@
+//! Great-circle geometry relative to the receiver location
+
+/// Mean Earth radius in kilometers
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Position of an aircraft relative to the receiver
+#[derive(Clone, Copy, Debug)]
+pub struct Polar {
+    /// Great-circle distance in kilometers
+    pub distance: f32,
+    /// Initial bearing from the receiver in degrees, 0 to 360 clockwise from north
+    pub bearing: f32,
+}
+
+impl Polar {
+    /// Distance (haversine) and bearing from `from` to `to`, both given as (lat, long) in degrees.
+    /// Identical points get bearing 0, and antipodal ones don't produce NaN
+    pub fn between(from: (f32, f32), to: (f32, f32)) -> Self {
+        let (lat1, long1) = (
+            f64::from(from.0).to_radians(),
+            f64::from(from.1).to_radians(),
+        );
+        let (lat2, long2) = (f64::from(to.0).to_radians(), f64::from(to.1).to_radians());
+
+        let d_lat = lat2 - lat1;
+        let d_long = long2 - long1;
+
+        // rounding can push it slightly outside [0, 1] for antipodal points
+        let a = ((d_lat / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (d_long / 2.0).sin().powi(2))
+        .clamp(0.0, 1.0);
+        let distance = 2.0 * EARTH_RADIUS_KM * a.sqrt().atan2((1.0 - a).sqrt());
+
+        let y = d_long.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_long.cos();
+        // atan2(0, 0) is 0, so identical points get bearing 0
+        let bearing = y.atan2(x).to_degrees().rem_euclid(360.0);
+        // rem_euclid of a tiny negative value rounds up to exactly 360
+        let bearing = if bearing >= 360.0 { 0.0 } else { bearing };
+
+        Self {
+            distance: distance as f32,
+            bearing: bearing as f32,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! GeoJSON representation of tracked aircraft, for GIS tools and map libraries
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::point::Point;
+
+pub const CONTENT_TYPE: &str = "application/geo+json";
+
+/// `FeatureCollection` with a `Point` feature for each aircraft.
+/// Serialized directly rather than through `serde_json::Value`, to keep `f32` coordinates short
+pub struct FeatureCollection(pub Vec<Point>);
+
+impl Serialize for FeatureCollection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Features<'a>(&'a [Point]);
+
+        impl Serialize for Features<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().map(Feature))
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", "FeatureCollection")?;
+        map.serialize_entry("features", &Features(&self.0))?;
+        map.end()
+    }
+}
+
+struct Feature<'a>(&'a Point);
+
+impl Serialize for Feature<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let point = self.0;
+
+        let mut map = serializer.serialize_map(Some(4))?;
+        map.serialize_entry("type", "Feature")?;
+        map.serialize_entry("id", &point.mode_s)?;
+        map.serialize_entry("geometry", &Geometry(point))?;
+        map.serialize_entry("properties", &Properties(point))?;
+        map.end()
+    }
+}
+
+struct Geometry<'a>(&'a Point);
+
+impl Serialize for Geometry<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", "Point")?;
+        // GeoJSON positions are [longitude, latitude]
+        map.serialize_entry("coordinates", &(self.0.long, self.0.lat))?;
+        map.end()
+    }
+}
+
+struct Properties<'a>(&'a Point);
+
+impl Serialize for Properties<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let point = self.0;
+
+        let mut map = serializer.serialize_map(Some(5))?;
+        map.serialize_entry("hex", &point.mode_s)?;
+        map.serialize_entry("callsign", &point.info.callsign)?;
+        map.serialize_entry("altitude", &point.info.altitude)?;
+        map.serialize_entry("ground_speed", &point.info.ground_speed)?;
+        map.serialize_entry("track", &point.info.track)?;
+        map.end()
+    }
+}
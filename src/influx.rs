@@ -0,0 +1,241 @@
+//! Export of live positions to InfluxDB (v2 write API) in line protocol, for Grafana dashboards
+
+use std::{fmt::Write, time::Duration};
+
+use axum::http::{header, Request};
+use hyper::{body, Body, Client};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::{
+    point::{Point, Update},
+    shutdown_requested, AppState,
+};
+
+/// Batch is written once it has that many lines...
+const BATCH_SIZE: usize = 1000;
+/// ...or that long after the previous write, whichever comes first
+const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct InfluxConfig {
+    /// Base URL of the server, e.g. `http://localhost:8086`. Only plain HTTP is supported
+    pub url: String,
+    pub token: Option<String>,
+    pub bucket: String,
+    pub org: Option<String>,
+}
+
+impl InfluxConfig {
+    fn write_url(&self) -> String {
+        let mut url = format!(
+            "{}/api/v2/write?precision=ns&bucket={}",
+            self.url.trim_end_matches('/'),
+            encode_query(&self.bucket)
+        );
+        if let Some(org) = &self.org {
+            let _ = write!(url, "&org={}", encode_query(org));
+        }
+
+        url
+    }
+}
+
+/// Writes live positions in batches until the server shuts down.
+/// Every position goes through the update queue, so bursts aren't coalesced
+/// like for latest-only clients; positions the exporter falls behind on are dropped
+/// and logged. Writes happen in the background and failed batches are dropped,
+/// so a slow or unavailable server never holds back collecting
+pub async fn run(config: InfluxConfig, state: AppState) {
+    let client = Client::new();
+    let write_url = config.write_url();
+
+    let mut receiver = state.queue.subscribe();
+    let mut batch = String::new();
+    let mut lines = 0;
+    let mut interval = tokio::time::interval(BATCH_INTERVAL);
+
+    let shutdown = shutdown_requested(state.shutdown.clone());
+    tokio::pin!(shutdown);
+
+    loop {
+        let flush = tokio::select! {
+            update = receiver.recv() => match update {
+                Ok(Update::Position(point)) if point.has_valid_position() => {
+                    write_line(&mut batch, &point);
+                    lines += 1;
+
+                    lines >= BATCH_SIZE
+                }
+                Ok(_) => false,
+                Err(RecvError::Lagged(dropped)) => {
+                    warn!("InfluxDB exporter fell behind, dropped {dropped} updates");
+                    false
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = interval.tick() => true,
+            () = &mut shutdown => break,
+        };
+
+        if flush && lines > 0 {
+            let request = write_request(&write_url, config.token.as_deref(), &mut batch);
+            tokio::spawn(send(client.clone(), request, lines));
+
+            lines = 0;
+            interval.reset();
+        }
+    }
+
+    // what's left is written out on shutdown, without waiting in the background
+    if lines > 0 {
+        let request = write_request(&write_url, config.token.as_deref(), &mut batch);
+        send(client, request, lines).await;
+    }
+
+    info!("InfluxDB exporter stopped");
+}
+
+/// `position,hex=ABC123 lat=..,lon=..,alt=..i,speed=.. <timestamp>`, absent fields are left out.
+/// So are non-finite speeds, which line protocol has no representation for
+fn write_line(batch: &mut String, point: &Point) {
+    let timestamp = point
+        .seen_at
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    // writing to a String can't fail
+    let _ = write!(
+        batch,
+        "position,hex={} lat={},lon={}",
+        escape_tag(&point.mode_s),
+        point.lat,
+        point.long
+    );
+    if let Some(altitude) = point.info.altitude {
+        let _ = write!(batch, ",alt={altitude}i");
+    }
+    if let Some(ground_speed) = point.info.ground_speed.filter(|speed| speed.is_finite()) {
+        let _ = write!(batch, ",speed={ground_speed}");
+    }
+    let _ = writeln!(batch, " {timestamp}");
+}
+
+/// Builds a write request, taking the lines out of the batch
+fn write_request(url: &str, token: Option<&str>, batch: &mut String) -> Request<Body> {
+    let mut request = Request::post(url).header(header::CONTENT_TYPE, "text/plain; charset=utf-8");
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Token {token}"));
+    }
+
+    request
+        .body(Body::from(std::mem::take(batch)))
+        .expect("write request is valid")
+}
+
+async fn send(client: Client<hyper::client::HttpConnector>, request: Request<Body>, lines: usize) {
+    let response = match tokio::time::timeout(WRITE_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            warn!("Failed to write {lines} positions to InfluxDB: {e}");
+            return;
+        }
+        Err(_) => {
+            warn!("Timed out writing {lines} positions to InfluxDB");
+            return;
+        }
+    };
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+
+        warn!(
+            "InfluxDB rejected {lines} positions with {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+}
+
+/// Tag values can't contain unescaped commas, equals signs and spaces
+fn escape_tag(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Percent-encodes everything but unreserved characters
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::point::AircraftInfo;
+
+    use super::*;
+
+    fn point(altitude: Option<i32>, ground_speed: Option<f32>) -> Point {
+        let mut point = Point::new(
+            "4CA2D1".into(),
+            (53.5, -6.25),
+            AircraftInfo {
+                altitude,
+                ground_speed,
+                ..Default::default()
+            },
+        );
+        point.seen_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        point
+    }
+
+    fn line(point: &Point) -> String {
+        let mut batch = String::new();
+        write_line(&mut batch, point);
+
+        batch
+    }
+
+    #[test]
+    fn writes_all_fields() {
+        assert_eq!(
+            line(&point(Some(35000), Some(451.5))),
+            "position,hex=4CA2D1 lat=53.5,lon=-6.25,alt=35000i,speed=451.5 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn leaves_out_absent_fields() {
+        assert_eq!(
+            line(&point(None, None)),
+            "position,hex=4CA2D1 lat=53.5,lon=-6.25 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn leaves_out_non_finite_speeds() {
+        for speed in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(
+                line(&point(Some(1200), Some(speed))),
+                "position,hex=4CA2D1 lat=53.5,lon=-6.25,alt=1200i 1700000000000000000\n",
+                "{speed}"
+            );
+        }
+    }
+}
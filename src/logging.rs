@@ -0,0 +1,262 @@
+//! Leveled logging: a small `tracing` subscriber that prints events with their span context,
+//! filtered by `RUST_LOG`-style directives, e.g. `info` or `warn,planewatch_map::source=debug`.
+//! Warnings and errors go to stderr, everything else to stdout
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Write},
+    num::NonZeroU64,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
+    subscriber::Subscriber,
+    Event, Level, Metadata,
+};
+
+pub const DEFAULT_FILTER: &str = "info";
+
+/// Installs the subscriber with the given directives.
+/// Exits the process with a readable message if they are malformed
+pub fn init(filter: &str) {
+    let filter = match Filter::from_str(filter) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Invalid log filter {filter:?}: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let logger = Logger {
+        filter,
+        next_id: AtomicU64::new(1),
+        spans: Mutex::default(),
+    };
+
+    tracing::subscriber::set_global_default(logger).expect("logging is initialized only once");
+}
+
+/// Maximum level by target prefix, the longest matching prefix wins
+#[derive(Debug)]
+struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter {
+            default: LevelFilter::ERROR,
+            targets: Vec::new(),
+        };
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse_level = |level: &str| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| format!("unknown level {level:?}"))
+            };
+
+            match directive.split_once('=') {
+                Some((target, level)) => filter
+                    .targets
+                    .push((target.to_owned(), parse_level(level)?)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+struct Span {
+    name: &'static str,
+    fields: String,
+    references: usize,
+}
+
+struct Logger {
+    filter: Filter,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<NonZeroU64, Span>>,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last
+    static CURRENT: RefCell<Vec<NonZeroU64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.filter
+            .targets
+            .iter()
+            .map(|&(_, level)| level)
+            .chain([self.filter.default])
+            .max()
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+
+        let id = NonZeroU64::new(self.next_id.fetch_add(1, Ordering::Relaxed))
+            .expect("span ids start at 1");
+        let span = Span {
+            name: attributes.metadata().name(),
+            fields: fields.rest,
+            references: 1,
+        };
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, span);
+
+        Id::from_non_zero_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(span) = spans.get_mut(&span.into_non_zero_u64()) {
+            let mut fields = Fields {
+                rest: std::mem::take(&mut span.fields),
+                ..Fields::default()
+            };
+            values.record(&mut fields);
+            span.fields = fields.rest;
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+
+        let mut line = format!("{:>5} ", metadata.level());
+
+        CURRENT.with(|current| {
+            let spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+            for span in current.borrow().iter().filter_map(|id| spans.get(id)) {
+                let _ = write!(line, "{}", span.name);
+                if !span.fields.is_empty() {
+                    let _ = write!(line, "{{{}}}", span.fields);
+                }
+                line.push_str(": ");
+            }
+        });
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let _ = write!(line, "{}: {}", metadata.target(), fields.message);
+        if !fields.rest.is_empty() {
+            let _ = write!(line, " {}", fields.rest);
+        }
+
+        if *metadata.level() <= Level::WARN {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        CURRENT.with(|current| current.borrow_mut().push(span.into_non_zero_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            if let Some(idx) = current
+                .iter()
+                .rposition(|id| *id == span.into_non_zero_u64())
+            {
+                current.remove(idx);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(span) = spans.get_mut(&span.into_non_zero_u64()) {
+            span.references += 1;
+        }
+
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = span.into_non_zero_u64();
+
+        let Some(span) = spans.get_mut(&id) else {
+            return false;
+        };
+        span.references -= 1;
+
+        if span.references == 0 {
+            spans.remove(&id);
+
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Formats the `message` field as is, and the others as `name=value`
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+            return;
+        }
+
+        if !self.rest.is_empty() {
+            self.rest.push(' ');
+        }
+        let _ = write!(self.rest, "{}={value:?}", field.name());
+    }
+}
@@ -1,108 +1,877 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    env,
     error::Error,
-    net::{SocketAddr, TcpStream},
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::Arc,
     thread::spawn,
+    time::Duration,
 };
 
 use axum::{
+    body::StreamBody,
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        ConnectInfo, State,
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, Query, State,
+    },
+    http::header,
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
     },
-    response::IntoResponse,
     routing::get,
     Json, Router,
 };
-use csv::ReaderBuilder;
+use futures_util::{stream, Stream, StreamExt};
 use smol_str::SmolStr;
-use tokio::sync::watch::{self, Receiver, Sender};
-use tower_http::{compression::CompressionLayer, services::ServeDir};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{
+        broadcast,
+        watch::{self, Receiver, Sender},
+    },
+    time::Instant,
+};
+use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
+    services::ServeDir,
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+use crate::config::Config;
+use crate::coverage::Coverage;
+use crate::filter::{AltitudeRange, BoundingBox, Filter};
+use crate::geo::Polar;
+use crate::map_image::TileCache;
+use crate::metrics::{Metrics, WsConnectionGuard};
+use crate::point::{
+    normalize_mode_s, AltitudeBands, CurrentAircraft, NamedPoint, Nearest, Point, TypedMessage,
+    Update,
+};
+use crate::recorder::Recorder;
+use crate::registry::Registry;
+use crate::station::{Build, Station};
+use crate::store::Store;
+
+mod aircraft_json;
+mod beast;
+mod binary;
+mod config;
+mod cors;
+mod country;
+mod coverage;
+mod filter;
+mod geo;
+mod geojson;
+mod influx;
+mod logging;
+mod map_image;
+mod metrics;
+mod mqtt;
+mod n_number;
+mod point;
+mod recorder;
+mod registry;
+mod sbs;
+mod snapshot;
+mod source;
+mod station;
+mod store;
 
 #[derive(Clone)]
 pub struct AppState {
-    points_seen: Arc<Mutex<VecDeque<(SmolStr, (f32, f32))>>>,
-    sender: Arc<Sender<(SmolStr, (f32, f32))>>,
+    store: Arc<Store>,
+    /// Receiver location as (lat, long), if configured
+    receiver: Option<(f32, f32)>,
+    /// Display name of the receiver, if configured
+    station_name: Option<Arc<str>>,
+    coverage: Arc<Coverage>,
+    /// Recently rendered map tiles
+    tiles: Arc<TileCache>,
+    /// Aircraft database, if configured
+    registry: Option<Arc<Registry>>,
+    /// Records received positions to a file, if enabled
+    recorder: Option<Arc<Recorder>>,
+    /// Latest position, `None` until the first one is received from the source
+    sender: Arc<Sender<Option<Update>>>,
+    /// One-shot events that a newer position must not supersede, see [`Update::is_event`]
+    events: broadcast::Sender<Update>,
+    /// Every update, for WebSocket clients that asked not to skip any, see [`Delivery::Queued`]
+    queue: broadcast::Sender<Update>,
+    metrics: Arc<Metrics>,
+    /// Upgrades are rejected once that many WebSocket connections are open
+    max_ws_connections: u64,
+    /// How often WebSocket clients are pinged; a client that doesn't answer
+    /// until the next ping is disconnected. Zero disables pings
+    ws_ping_interval: Duration,
+    /// WebSocket clients get at most one position per aircraft this often. Zero sends everything
+    ws_throttle: Duration,
+    /// WebSocket clients that don't take a message for that long are disconnected.
+    /// Zero disables the timeout
+    ws_send_timeout: Duration,
+    /// Thresholds for the `altitude_band` of aircraft
+    altitude_bands: AltitudeBands,
+    /// `/healthz` fails once no record is read from the source for that long
+    health_max_silence: Duration,
+    /// Records from the live source beyond that many per second are dropped, unlimited if `None`
+    max_record_rate: Option<u32>,
+    /// Whether `/stats` lists the addresses of WebSocket clients
+    stats_client_addrs: bool,
+    /// Becomes `true` once the server starts shutting down
+    shutdown: Receiver<bool>,
 }
 
-const POINTS_HISTORY_LIMIT: usize = 40000;
+impl AppState {
+    /// Broadcasts the update to live clients
+    fn publish(&self, update: Update) {
+        // cloning points is only worth it if someone is listening
+        if self.queue.receiver_count() > 0 {
+            let _ = self.queue.send(update.clone());
+        }
+
+        if update.is_event() {
+            // no one listening isn't an error
+            let _ = self.events.send(update);
+        } else {
+            self.sender.send_replace(Some(update));
+        }
+    }
+
+    /// Subscribes to the latest position along with every event
+    fn subscribe_latest(&self) -> Latest {
+        Latest {
+            positions: self.sender.subscribe(),
+            events: self.events.subscribe(),
+        }
+    }
+
+    fn station(&self) -> Station<'_> {
+        Station {
+            name: self.station_name.as_deref(),
+            location: self.receiver,
+        }
+    }
+}
+
+/// Aircraft returned by `/nearest` unless `k` is given
+const DEFAULT_NEAREST: usize = 10;
+/// Upper bound for `k` in `/nearest`, to keep responses small
+const MAX_NEAREST: usize = 100;
+
+const JSONL_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Hand-written OpenAPI description of the HTTP API, to be kept in sync with the handlers
+/// and the serialization in `point.rs`
+const OPENAPI: &str = include_str!("openapi.json");
+
+/// Events each latest-only subscriber can fall behind on, they're rare compared to positions
+const EVENT_QUEUE_CAPACITY: usize = 256;
+
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let points_seen = Arc::new(Mutex::new(VecDeque::with_capacity(POINTS_HISTORY_LIMIT)));
-    let (sender, _receiver) = watch::channel((SmolStr::default(), (f32::NAN, f32::NAN)));
+    let config = Config::load();
+    logging::init(&config.log_filter);
+
+    let registry = config
+        .aircraft_db
+        .as_ref()
+        .map(|path| match Registry::load(path) {
+            Ok(registry) => {
+                info!(
+                    "Loaded {} aircraft from {}",
+                    registry.count(),
+                    path.display()
+                );
+
+                Arc::new(registry)
+            }
+            Err(e) => {
+                error!("Failed to load aircraft database {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        });
+    let cors = match cors::layer(config.cors_origins.as_deref()) {
+        Ok(cors) => cors,
+        Err(e) => {
+            error!("Invalid value for --cors-origins / PLANEWATCH_CORS_ORIGINS: {e}");
+            std::process::exit(1);
+        }
+    };
+    let recorder = config
+        .record
+        .as_ref()
+        .map(|path| match Recorder::open(path) {
+            Ok(recorder) => {
+                info!("Recording positions to {}", path.display());
+
+                Arc::new(recorder)
+            }
+            Err(e) => {
+                error!("Failed to open recording {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        });
+
+    config.log_summary();
+
+    let Config {
+        source,
+        bind: bind_addr,
+        expire_after,
+        receiver,
+        station_name,
+        history_limit,
+        history_retention,
+        track_length,
+        max_ws_connections,
+        ws_ping_interval,
+        ws_throttle,
+        ws_send_timeout,
+        ws_queue_capacity,
+        request_timeout,
+        health_max_silence,
+        max_record_rate,
+        stats_client_addrs,
+        altitude_bands,
+        mqtt,
+        influx,
+        state_file,
+        ..
+    } = config;
+
+    let (sender, _receiver) = watch::channel(None);
     let sender = Arc::new(sender);
+    let (events, _) = broadcast::channel(EVENT_QUEUE_CAPACITY);
+    let (queue, _) = broadcast::channel(ws_queue_capacity);
+    let (shutdown_sender, shutdown) = watch::channel(false);
 
     let state = AppState {
-        points_seen: Arc::clone(&points_seen),
-        sender: Arc::clone(&sender),
+        store: Arc::new(Store::new(
+            history_limit,
+            history_retention,
+            track_length,
+            expire_after,
+        )),
+        receiver,
+        station_name: station_name.map(Arc::from),
+        coverage: Arc::default(),
+        tiles: Arc::default(),
+        registry,
+        recorder,
+        sender,
+        events,
+        queue,
+        metrics: Arc::new(Metrics::default()),
+        max_ws_connections,
+        ws_ping_interval,
+        ws_throttle,
+        ws_send_timeout,
+        altitude_bands,
+        health_max_silence,
+        max_record_rate,
+        stats_client_addrs,
+        shutdown,
     };
+    if let Some(path) = &state_file {
+        match snapshot::load(path, expire_after) {
+            Ok(points) => {
+                info!("Restored {} aircraft from {}", points.len(), path.display());
+
+                source::restore(points, &state);
+            }
+            Err(e) => warn!("Failed to restore state from {}: {e}", path.display()),
+        }
+    }
+
+    let source_state = state.clone();
+    let snapshot_store = state.store.clone();
+    let recorder = state.recorder.clone();
+    let sweep_state = state.clone();
+    let mqtt_state = state.clone();
+    let influx_state = state.clone();
 
     let assets_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
 
     let app = Router::new()
         .fallback_service(ServeDir::new(assets_dir))
         .route("/points_history", get(points_history))
+        .route("/aircraft", get(aircraft))
+        .route("/aircraft.geojson", get(aircraft_geojson))
+        .route("/aircraft/:hex/track", get(aircraft_track))
+        .route("/coverage", get(coverage))
+        .route("/station", get(station))
+        .route("/version", get(version))
+        .route("/map.png", get(map_png))
+        .route("/nearest", get(nearest))
+        .route("/tiles/:z/:x/:y", get(tile))
         .route("/ws", get(ws_handler))
-        .layer(CompressionLayer::new())
+        .route("/events", get(events_handler))
+        .route("/stream.jsonl", get(jsonl_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz))
+        .route("/stats", get(stats))
+        .route("/openapi.json", get(openapi))
+        // event streams would be buffered by the compressor, delaying events indefinitely
+        .layer(
+            CompressionLayer::new().compress_when(
+                DefaultPredicate::new()
+                    .and(NotForContentType::const_new("text/event-stream"))
+                    .and(NotForContentType::const_new(JSONL_CONTENT_TYPE)),
+            ),
+        )
+        .layer(cors)
+        // only covers producing the response, streams and WebSockets aren't cut off by it
+        .layer(TimeoutLayer::new(request_timeout))
+        .layer(TraceLayer::new_for_http())
         .with_state(state);
 
     spawn(move || {
-        println!("Created background task");
-
-        let stream = TcpStream::connect("127.0.0.1:30003").expect("failed to connect to source");
-        let mut reader = ReaderBuilder::new().flexible(true).from_reader(stream);
-
-        for record in reader.records() {
-            let record = record.expect("failed to parse source info");
-
-            let lat_long = record
-                .get(14)
-                .map(str::parse::<f32>)
-                .map(Result::ok)
-                .flatten()
-                .zip(
-                    record
-                        .get(15)
-                        .map(str::parse::<f32>)
-                        .map(Result::ok)
-                        .flatten(),
-                );
+        debug!("Created background task");
+
+        source::run(source, source_state);
+    });
 
-            let mode_s = SmolStr::new(record.get(4).unwrap_or_default());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
 
-            if let Some((lat, long)) = lat_long {
-                let mut points_seen = points_seen.lock().expect("points lock poisoned");
+        loop {
+            interval.tick().await;
+            expire_aircraft(&sweep_state);
+        }
+    });
 
-                points_seen.push_back((mode_s.clone(), (lat, long)));
+    if let Some(recorder) = recorder.clone() {
+        // records are only flushed as new ones come in otherwise,
+        // so the last ones before a quiet period would stay buffered
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(recorder::FLUSH_INTERVAL);
 
-                while points_seen.len() >= POINTS_HISTORY_LIMIT {
-                    points_seen.pop_front();
+            loop {
+                interval.tick().await;
+                if let Err(e) = recorder.flush() {
+                    error!("Failed to flush recording: {e}");
                 }
+            }
+        });
+    }
+
+    if let Some(path) = state_file.clone() {
+        let store = snapshot_store.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+            // the first tick is immediate, and there's nothing new to save yet
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                save_snapshot(path.clone(), store.clone()).await;
+            }
+        });
+    }
+
+    if let Some(mqtt) = mqtt {
+        tokio::spawn(mqtt::run(mqtt, mqtt_state));
+    }
+
+    if let Some(influx) = influx {
+        tokio::spawn(influx::run(influx, influx_state));
+    }
+
+    let (server, bind_addr) = match axum::Server::try_bind(&bind_addr) {
+        Ok(server) => (server, bind_addr),
+        // the IPv6 wildcard can't be bound on hosts with IPv6 disabled, the IPv4 one still can
+        Err(e) if bind_addr.ip() == Ipv6Addr::UNSPECIFIED && !is_port_unavailable(&e) => {
+            let fallback = SocketAddr::from((Ipv4Addr::UNSPECIFIED, bind_addr.port()));
+            warn!("Failed to bind to {bind_addr}, IPv6 may be disabled: {e}");
+            info!(
+                "Falling back to {fallback}, set PLANEWATCH_BIND={fallback} to use it right away"
+            );
 
-                sender.send_replace((mode_s, (lat, long)));
+            match axum::Server::try_bind(&fallback) {
+                Ok(server) => (server, fallback),
+                Err(e) => {
+                    error!(
+                        "Failed to bind to {fallback}: {e}. \
+                         Set another address with --bind / PLANEWATCH_BIND"
+                    );
+                    std::process::exit(1);
+                }
             }
         }
-    });
+        Err(e) => {
+            error!(
+                "Failed to bind to {bind_addr}: {e}. \
+                 Set another address with --bind / PLANEWATCH_BIND"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    info!("Listening on {bind_addr}");
 
-    axum::Server::bind(&"[::]:12345".parse().unwrap())
+    server
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            info!("Shutting down");
+
+            shutdown_sender.send_replace(true);
+        })
         .await?;
 
+    if let Some(path) = state_file {
+        save_snapshot(path, snapshot_store).await;
+    }
+
+    // the source thread may be blocked on reading, so buffered records are written out here
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.flush() {
+            error!("Failed to flush recording: {e}");
+        }
+    }
+
     Ok(())
 }
 
-async fn points_history(State(state): State<AppState>) -> impl IntoResponse {
-    let points = state
-        .points_seen
-        .lock()
-        .expect("lock is poisoned")
-        .make_contiguous()
-        .to_vec();
+/// Resolves once SIGINT (Ctrl-C) or SIGTERM is received
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl-C handler");
+    };
+
+    let terminate = async {
+        signal(SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Resolves once the server starts shutting down
+async fn shutdown_requested(mut shutdown: Receiver<bool>) {
+    // sender is only dropped after shutdown is sent, so the result can be ignored
+    let _ = shutdown.wait_for(|&shutdown| shutdown).await;
+}
+
+/// Writes current aircraft to the state file, off the async runtime
+async fn save_snapshot(path: PathBuf, store: Arc<Store>) {
+    let saved = tokio::task::spawn_blocking(move || {
+        snapshot::save(&path, &store.current_points()).map_err(|e| (path, e))
+    })
+    .await;
+
+    match saved {
+        Ok(Ok(())) => {}
+        Ok(Err((path, e))) => error!("Failed to save state to {}: {e}", path.display()),
+        Err(e) => error!("Failed to save state: {e}"),
+    }
+}
+
+/// Drops aircraft that weren't seen for longer than `expire_after`,
+/// notifying live clients about them
+fn expire_aircraft(state: &AppState) {
+    let expired = state.store.expire();
+
+    if !expired.is_empty() {
+        debug!("{} aircraft expired", expired.len());
+
+        state.publish(Update::Expired(expired));
+    }
+}
+
+/// Whether binding failed because of the port (taken or privileged)
+/// rather than the address, in which case other addresses won't help either
+fn is_port_unavailable(error: &hyper::Error) -> bool {
+    error
+        .source()
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .is_some_and(|source| {
+            matches!(
+                source.kind(),
+                io::ErrorKind::AddrInUse | io::ErrorKind::PermissionDenied
+            )
+        })
+}
+
+/// Latest state of each aircraft that is currently in range, keyed by hex ident
+async fn aircraft(State(state): State<AppState>) -> impl IntoResponse {
+    Json::from(CurrentAircraft(state.store.current_points()))
+}
+
+/// Currently tracked aircraft as a GeoJSON `FeatureCollection`
+async fn aircraft_geojson(State(state): State<AppState>) -> impl IntoResponse {
+    let collection = geojson::FeatureCollection(state.store.current_points());
+
+    (
+        [(header::CONTENT_TYPE, geojson::CONTENT_TYPE)],
+        serde_json::to_string(&collection).expect("GeoJSON serialization is infallible"),
+    )
+}
+
+/// Recent path of a single aircraft as `[[lat, long, timestamp], ...]`, oldest first
+async fn aircraft_track(Path(mode_s): Path<SmolStr>, State(state): State<AppState>) -> Response {
+    match normalize_mode_s(&mode_s).and_then(|normalized| state.store.track(&normalized)) {
+        Some(track) => Json::from(track).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("aircraft {mode_s} is not tracked"),
+        )
+            .into_response(),
+    }
+}
 
-    Json::from(points)
+/// Farthest position seen in each of the 16 compass sectors since startup.
+/// Sectors stay empty unless the receiver location is configured
+async fn coverage(State(state): State<AppState>) -> impl IntoResponse {
+    Json::from(state.coverage.snapshot())
+}
+
+/// Current traffic rendered to a PNG image, see [`map_image::render`].
+/// Size is set with `width` and `height`, and the area with the same bounds as in `/ws`
+async fn map_png(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let size = |name: &str, default: u32| match query.get(name) {
+        None => Ok(default),
+        Some(value) => value
+            .parse::<u32>()
+            .ok()
+            .filter(|size| (1..=map_image::MAX_SIZE).contains(size))
+            .ok_or_else(|| {
+                format!(
+                    "invalid {name} {value:?}, expected 1 to {}",
+                    map_image::MAX_SIZE
+                )
+            }),
+    };
+
+    let params = size("width", map_image::DEFAULT_WIDTH).and_then(|width| {
+        let height = size("height", map_image::DEFAULT_HEIGHT)?;
+        let bbox = BoundingBox::from_query(&query)?;
+
+        let empty =
+            |min: Option<f32>, max: Option<f32>| min.zip(max).is_some_and(|(min, max)| min >= max);
+        if empty(bbox.min_lat, bbox.max_lat) || empty(bbox.min_long, bbox.max_long) {
+            return Err("bounds must not be empty".to_owned());
+        }
+
+        Ok((width, height, bbox))
+    });
+    let (width, height, bbox) = match params {
+        Ok(params) => params,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let points: Vec<Point> = state
+        .store
+        .current_points()
+        .into_iter()
+        .filter(|point| bbox.contains(point))
+        .collect();
+    let receiver = state.receiver;
+    // rendering a large image takes a while, and would hold up other requests on the runtime
+    let png = match tokio::task::spawn_blocking(move || {
+        map_image::render(&points, bbox, receiver, width, height)
+    })
+    .await
+    {
+        Ok(png) => png,
+        Err(e) => {
+            error!("Failed to render map: {e}");
+
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    ([(header::CONTENT_TYPE, map_image::CONTENT_TYPE)], png).into_response()
+}
+
+/// Slippy map tile of current traffic at `/tiles/{z}/{x}/{y}.png`, see [`map_image::render_tile`].
+/// Tiles are cached for [`map_image::TILE_TTL`], and ones outside the map are not found
+async fn tile(
+    State(state): State<AppState>,
+    Path((zoom, x, y)): Path<(String, String, String)>,
+) -> Response {
+    let tile = y.strip_suffix(".png").and_then(|y| {
+        let zoom = zoom
+            .parse::<u8>()
+            .ok()
+            .filter(|&zoom| zoom <= map_image::MAX_ZOOM)?;
+        let tiles = 1u32 << zoom;
+        let x = x.parse::<u32>().ok().filter(|&x| x < tiles)?;
+        let y = y.parse::<u32>().ok().filter(|&y| y < tiles)?;
+
+        Some((zoom, x, y))
+    });
+    let Some((zoom, x, y)) = tile else {
+        return (StatusCode::NOT_FOUND, "no such tile").into_response();
+    };
+
+    let png = match state.tiles.get((zoom, x, y)) {
+        Some(png) => png,
+        None => {
+            let points = state.store.current_points();
+            // a map asks for many tiles at once, rendering them on the runtime would hold up
+            // other requests. The same tile may occasionally be rendered twice
+            let png = match tokio::task::spawn_blocking(move || {
+                map_image::render_tile(&points, zoom, x, y)
+            })
+            .await
+            {
+                Ok(png) => png,
+                Err(e) => {
+                    error!("Failed to render tile: {e}");
+
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+
+            state.tiles.insert((zoom, x, y), png.clone());
+            png
+        }
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, map_image::CONTENT_TYPE.to_owned()),
+            (
+                header::CACHE_CONTROL,
+                format!("max-age={}", map_image::TILE_TTL.as_secs()),
+            ),
+        ],
+        png,
+    )
+        .into_response()
+}
+
+/// Up to `k` currently tracked aircraft closest to `lat` and `lon`, nearest first,
+/// see [`Nearest`]. `k` is 10 by default and capped at [`MAX_NEAREST`]
+async fn nearest(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let coordinate = |name: &str, limit: f32| match query.get(name) {
+        None => Err(format!("missing {name}")),
+        Some(value) => value
+            .parse::<f32>()
+            .ok()
+            .filter(|coordinate| (-limit..=limit).contains(coordinate))
+            .ok_or_else(|| format!("invalid {name} {value:?}, expected -{limit} to {limit}")),
+    };
+
+    let params = coordinate("lat", 90.0).and_then(|lat| {
+        let long = coordinate("lon", 180.0)?;
+        let k = match query.get("k") {
+            None => DEFAULT_NEAREST,
+            Some(value) => value
+                .parse::<usize>()
+                .map_err(|e| format!("invalid k {value:?}: {e}"))?,
+        };
+
+        Ok(((lat, long), k.min(MAX_NEAREST)))
+    });
+    let (location, k) = match params {
+        Ok(params) => params,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let mut nearest: Vec<(Point, Polar)> = state
+        .store
+        .current_points()
+        .into_iter()
+        .map(|point| {
+            let polar = Polar::between(location, (point.lat, point.long));
+            (point, polar)
+        })
+        .collect();
+    nearest.sort_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance));
+    nearest.truncate(k);
+
+    Json::from(Nearest(nearest)).into_response()
+}
+
+/// All points received recently, oldest first. JSON by default, or CSV in the recording format
+/// (see [`recorder::HEADER`]) when requested with `?format=csv` or `Accept: text/csv`.
+/// Can be limited to an altitude range with `?min_alt=` and `?max_alt=`, see [`AltitudeRange`]
+async fn points_history(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let csv = match query.get("format").map(String::as_str) {
+        Some("csv") => true,
+        Some("json") => false,
+        Some(format) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown format {format:?}, expected \"json\" or \"csv\""),
+            )
+                .into_response()
+        }
+        None => headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/csv")),
+    };
+
+    let altitude = match AltitudeRange::from_query(&query) {
+        Ok(altitude) => altitude,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let history = state.store.history();
+    let points = history.points().filter(|point| altitude.contains(point));
+
+    if !csv {
+        return Json::from(points.collect::<Vec<_>>()).into_response();
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // writing to a Vec can't fail
+    let _ = writer.write_record(recorder::HEADER);
+    for point in points {
+        let _ = writer.write_record(recorder::row(point));
+    }
+    let body = writer.into_inner().expect("writing to a Vec can't fail");
+
+    ([(header::CONTENT_TYPE, "text/csv")], body).into_response()
+}
+
+/// Metrics in Prometheus text exposition format
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(state.store.current_points().len()),
+    )
+}
+
+/// Receiver name, location and server version, see [`Station`]
+async fn station(State(state): State<AppState>) -> Response {
+    Json::from(state.station()).into_response()
+}
+
+/// Version, git commit and build time of the running server
+async fn version() -> impl IntoResponse {
+    Json::from(Build)
+}
+
+/// OpenAPI description of the HTTP API, for generating clients
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+/// Feed diagnostics since startup as JSON, including SBS messages by transmission type,
+/// e.g. to tell whether the feed carries any airborne positions (type 3) at all
+async fn stats(State(state): State<AppState>) -> impl IntoResponse {
+    Json::from(state.metrics.stats(state.stats_client_addrs))
+}
+
+/// Liveness of the feed: 200 if a record was read from the source within `--health-max-silence`,
+/// 503 otherwise, so that orchestrators can restart the container when the feed is dead
+async fn healthz(State(state): State<AppState>) -> impl IntoResponse {
+    match state.metrics.since_last_record() {
+        Some(silence) if silence <= state.health_max_silence => (
+            StatusCode::OK,
+            format!("ok, last record {}s ago", silence.as_secs()),
+        ),
+        Some(silence) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("feed is stale, last record {}s ago", silence.as_secs()),
+        ),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no records read from the source yet".to_owned(),
+        ),
+    }
+}
+
+/// Server-Sent Events alternative to `/ws` for read-only consumers:
+/// sends latest known positions first, then streams the same live updates
+async fn events_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    info!("{addr} subscribed to events");
+
+    // subscribe before taking the snapshot, so that no update is missed in between
+    let latest = state.subscribe_latest();
+    let snapshot = state
+        .store
+        .current_points()
+        .into_iter()
+        .map(Update::Position);
+
+    let updates = stream::unfold(latest, |mut latest| async move {
+        let update = latest.next().await?;
+
+        Some((update, latest))
+    });
+
+    let events = stream::iter(snapshot)
+        .chain(updates)
+        .map(|update| {
+            Ok(Event::default()
+                .json_data(update)
+                .expect("update serialization is infallible"))
+        })
+        // end the stream on shutdown, otherwise graceful shutdown would wait for it forever
+        .take_until(shutdown_requested(state.shutdown));
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Newline-delimited JSON stream of live positions, one object per line,
+/// for logging and consumers like `curl | jq`
+async fn jsonl_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    info!("{addr} subscribed to JSON Lines stream");
+
+    let lines = stream::unfold(state.subscribe_latest(), |mut latest| async move {
+        let update = latest.next().await?;
+
+        Some((update, latest))
+    })
+    .filter_map(|update| async move {
+        let Update::Position(point) = update else {
+            return None;
+        };
+
+        let mut line =
+            serde_json::to_string(&NamedPoint(&point)).expect("point serialization is infallible");
+        line.push('\n');
+
+        Some(Ok::<_, Infallible>(line))
+    })
+    // end the stream on shutdown, otherwise graceful shutdown would wait for it forever
+    .take_until(shutdown_requested(state.shutdown));
+
+    (
+        [(header::CONTENT_TYPE, JSONL_CONTENT_TYPE)],
+        StreamBody::new(lines),
+    )
 }
 
 /// The handler for the HTTP request (this gets called when the HTTP GET lands at the start
@@ -114,46 +883,714 @@ async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
-    println!("{addr} connected.");
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    info!("{addr} connected");
+
+    let (bbox, altitude) = match BoundingBox::from_query(&query)
+        .and_then(|bbox| Ok((bbox, AltitudeRange::from_query(&query)?)))
+    {
+        Ok(params) => params,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let follow = match query.get("follow").map(|hex| (hex, normalize_mode_s(hex))) {
+        None => None,
+        Some((_, Some(mode_s))) => Some(mode_s),
+        Some((hex, None)) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid follow {hex:?}")).into_response()
+        }
+    };
+
+    let format = match query.get("format").map(String::as_str) {
+        None | Some("json") => Format::Json,
+        Some("typed") => Format::Typed,
+        Some("binary") => Format::Binary,
+        Some(format) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown format {format:?}, expected \"json\", \"typed\" or \"binary\""),
+            )
+                .into_response()
+        }
+    };
+
+    let delivery = match query.get("delivery").map(String::as_str) {
+        // followers want every update of their aircraft, and there are few of them
+        None if follow.is_some() => Delivery::Queued,
+        None | Some("latest") => Delivery::Latest,
+        Some("queued") => Delivery::Queued,
+        Some(delivery) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown delivery {delivery:?}, expected \"latest\" or \"queued\""),
+            )
+                .into_response()
+        }
+    };
+
+    let Some(connection) = state.metrics.ws_connection(state.max_ws_connections, addr) else {
+        warn!("Rejecting {addr}: too many WebSocket connections");
+
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many WebSocket connections",
+        )
+            .into_response();
+    };
+
+    // subscribe before taking the snapshot, so that no update is missed in between
+    let updates = match delivery {
+        Delivery::Latest => Updates::Latest(state.subscribe_latest()),
+        Delivery::Queued => Updates::Queued(state.queue.subscribe()),
+    };
+    let snapshot = state.store.current_points();
+
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, state.sender.subscribe()))
+    let filter = Filter {
+        bbox,
+        altitude,
+        // same as the `hex` command, for detail panels that only show one aircraft
+        mode_s: follow,
+    };
+
+    ws.protocols(Format::SUBPROTOCOLS.map(|(protocol, _)| protocol))
+        .on_upgrade(move |socket| {
+            // negotiated subprotocol takes precedence over the query
+            let format = socket
+                .protocol()
+                .and_then(|protocol| protocol.to_str().ok())
+                .and_then(Format::from_subprotocol)
+                .unwrap_or(format);
+
+            let client = Client {
+                format,
+                known: HashSet::new(),
+                send_timeout: state.ws_send_timeout,
+                connection,
+            };
+
+            handle_socket(socket, client, state, updates, snapshot, filter)
+                .instrument(info_span!("ws", client = %addr))
+        })
+}
+
+/// How updates are encoded for a WebSocket client. Chosen either with `?format=`,
+/// or by negotiating one of the versioned subprotocols, see [`Format::SUBPROTOCOLS`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Text messages, same as in `/events`
+    Json,
+    /// Text messages with an explicit `type`, including aircraft lifecycle events,
+    /// see [`TypedMessage`]
+    Typed,
+    /// Binary messages, see [`binary`] for the layout
+    Binary,
+}
+
+impl Format {
+    /// Subprotocols selecting each format. If a client offers several, the first one
+    /// in its `Sec-WebSocket-Protocol` header wins, the order here doesn't matter
+    const SUBPROTOCOLS: [(&'static str, Format); 3] = [
+        ("planewatch.v2", Format::Typed),
+        (binary::SUBPROTOCOL, Format::Binary),
+        ("planewatch.v1", Format::Json),
+    ];
+
+    fn from_subprotocol(protocol: &str) -> Option<Self> {
+        Self::SUBPROTOCOLS
+            .into_iter()
+            .find_map(|(name, format)| (name == protocol).then_some(format))
+    }
+}
+
+/// How live updates reach a WebSocket client, chosen with `?delivery=`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Delivery {
+    /// Only the latest position at each wakeup, skipping ones that came in between,
+    /// but every event. Good enough for maps, and slow clients can't fall behind
+    Latest,
+    /// Every update, queued per client up to `--ws-queue-capacity`. Clients that fall
+    /// further behind are told how many updates they missed, see [`Received::Lagged`]
+    Queued,
+}
+
+/// Subscription to live updates, according to the client's [`Delivery`]
+enum Updates {
+    Latest(Latest),
+    Queued(broadcast::Receiver<Update>),
+}
+
+/// What a subscription yields next
+enum Received {
+    Update(Update),
+    /// Client fell behind the queue, and that many updates were dropped
+    Lagged(u64),
+}
+
+impl Updates {
+    /// Waits for the next update that can be forwarded to the client,
+    /// `None` once the channel is closed
+    async fn next(&mut self) -> Option<Received> {
+        match self {
+            Updates::Latest(latest) => latest.next().await.map(Received::Update),
+            Updates::Queued(receiver) => loop {
+                match receiver.recv().await {
+                    Ok(Update::Position(point)) if !point.has_valid_position() => continue,
+                    Ok(update) => return Some(Received::Update(update)),
+                    Err(broadcast::error::RecvError::Lagged(dropped)) => {
+                        return Some(Received::Lagged(dropped))
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+        }
+    }
+}
+
+/// Connected WebSocket client
+struct Client {
+    format: Format,
+    /// Aircraft the client knows about, to tell it when a new one shows up
+    known: HashSet<SmolStr>,
+    /// Sends that take longer than that fail, so that a client that stopped reading
+    /// doesn't hold the connection forever. Zero disables the timeout
+    send_timeout: Duration,
+    /// Keeps the connection counted until the socket is done, and counts messages sent to it
+    connection: WsConnectionGuard,
+}
+
+/// Subscription to the latest position, which newer ones replace, along with every event
+struct Latest {
+    positions: Receiver<Option<Update>>,
+    events: broadcast::Receiver<Update>,
+}
+
+impl Latest {
+    /// Waits for the next update that can be forwarded to clients, skipping the initial
+    /// empty value and points without a valid position. Pending events go first,
+    /// `None` once the channels are closed
+    async fn next(&mut self) -> Option<Update> {
+        loop {
+            tokio::select! {
+                biased;
+
+                event = self.events.recv() => match event {
+                    Ok(update) => return Some(update),
+                    Err(broadcast::error::RecvError::Lagged(dropped)) => {
+                        warn!("Fell behind on events, {dropped} were dropped");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                changed = self.positions.changed() => {
+                    changed.ok()?;
+
+                    match &*self.positions.borrow_and_update() {
+                        Some(Update::Position(point)) if !point.has_valid_position() => {}
+                        Some(update) => return Some(update.clone()),
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
 }
 
 /// Actual websocket statemachine (one will be spawned per connection)
 async fn handle_socket(
     mut socket: WebSocket,
-    who: SocketAddr,
-    mut receiver: Receiver<(SmolStr, (f32, f32))>,
+    mut client: Client,
+    state: AppState,
+    mut updates: Updates,
+    snapshot: Vec<Point>,
+    mut filter: Filter,
 ) {
-    loop {
-        match receiver.changed().await {
-            Ok(()) => {
-                let (mode_s, (lat, long)) = receiver.borrow().clone();
-                println!("got change");
-
-                match socket
-                    .send(Message::Text(format!("[\"{mode_s}\",[{lat},{long}]]")))
-                    .await
-                {
-                    Ok(()) => {
-                        println!("update sent to {who}");
+    let station = station_message(client.format, state.station());
+    if let Err(e) = send(&mut socket, &client, station).await {
+        warn!("Got error while sending station info: {e}");
+        disconnected(&client);
+
+        return;
+    }
+
+    // aircraft from the snapshot are already tracked, so they aren't announced as new
+    client.known = snapshot.iter().map(|point| point.mode_s.clone()).collect();
+
+    // send latest known positions first, so that the client doesn't start with an empty map
+    for point in snapshot.into_iter().filter(|point| filter.contains(point)) {
+        match send_update(&mut socket, &mut client, &Update::Position(point)).await {
+            Ok(()) => {}
+            Err(e) if is_recoverable(&e) => debug!("Skipped snapshot position: {e}"),
+            Err(e) => {
+                warn!("Got error while sending snapshot: {e}");
+                disconnected(&client);
+
+                return;
+            }
+        }
+    }
+
+    debug!("Snapshot sent");
+
+    let shutdown = shutdown_requested(state.shutdown);
+    tokio::pin!(shutdown);
+
+    let pings_enabled = !state.ws_ping_interval.is_zero();
+    // interval can't be zero, its ticks are ignored when pings are disabled anyway
+    let ping_period = state.ws_ping_interval.max(Duration::from_secs(1));
+    let mut ping = tokio::time::interval_at(Instant::now() + ping_period, ping_period);
+    let mut awaiting_pong = false;
+
+    // latest position of each aircraft received since the last flush, when throttling.
+    // clients that asked for every update aren't throttled
+    let throttling = !state.ws_throttle.is_zero() && matches!(updates, Updates::Latest(_));
+    let mut pending: HashMap<SmolStr, Point> = HashMap::new();
+    let mut flush = tokio::time::interval(state.ws_throttle.max(Duration::from_millis(1)));
+
+    // close frame to send once done, for connections that are still usable
+    let close = 'connection: loop {
+        let update = tokio::select! {
+            update = updates.next() => update,
+            _ = ping.tick(), if pings_enabled => {
+                if awaiting_pong {
+                    info!("Client didn't answer ping, closing");
+
+                    break close_frame(close_code::AWAY, "ping timeout");
+                }
+
+                match send(&mut socket, &client, Message::Ping(Vec::new())).await {
+                    Ok(()) => awaiting_pong = true,
+                    Err(e) if is_recoverable(&e) => debug!("Skipped ping: {e}"),
+                    Err(e) => {
+                        warn!("Got error while sending ping: {e}");
+
+                        break None;
+                    }
+                }
+
+                continue;
+            }
+            _ = flush.tick(), if throttling => {
+                // filter could've changed since the positions were buffered
+                for point in pending.drain().map(|(_, point)| point) {
+                    if !filter.contains(&point) {
+                        continue;
+                    }
+
+                    match send_update(&mut socket, &mut client, &Update::Position(point)).await {
+                        Ok(()) => {}
+                        Err(e) if is_recoverable(&e) => debug!("Skipped update: {e}"),
+                        Err(e) => {
+                            warn!("Got error while sending: {e}");
+
+                            break 'connection None;
+                        }
+                    }
+                }
+
+                continue;
+            }
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(Message::Text(command))) => {
+                        if let Err(e) = filter.apply_command(&command) {
+                            warn!("Ignoring malformed command: {e}");
+                        }
                     }
+                    Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                    Some(Ok(Message::Close(_))) | None => break None,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        warn!("Got error while receiving: {e}");
+
+                        break None;
+                    }
+                }
+
+                continue;
+            }
+            () = &mut shutdown => {
+                debug!("Closing on shutdown");
+
+                break close_frame(close_code::AWAY, "server shutting down");
+            }
+        };
+
+        match update {
+            Some(Received::Update(update)) if !filter.matches(&update) => {}
+            Some(Received::Update(Update::Position(point))) if throttling => {
+                pending.insert(point.mode_s.clone(), point);
+            }
+            Some(Received::Update(update)) => {
+                if let Update::Expired(expired) = &update {
+                    for mode_s in expired {
+                        pending.remove(mode_s);
+                    }
+                }
+
+                match send_update(&mut socket, &mut client, &update).await {
+                    Ok(()) => trace!("Update sent"),
+                    // the next update supersedes the skipped one anyway
+                    Err(e) if is_recoverable(&e) => debug!("Skipped update: {e}"),
                     Err(e) => {
-                        eprintln!("Got error while sending: {e}");
+                        warn!("Got error while sending: {e}");
 
-                        break;
+                        break None;
+                    }
+                }
+
+                // typed messages already include a lost event for each expired aircraft
+                let lost = match (&update, &filter.mode_s) {
+                    (Update::Expired(expired), Some(followed))
+                        if client.format != Format::Typed && expired.contains(followed) =>
+                    {
+                        Some(followed)
+                    }
+                    _ => None,
+                };
+                if let Some(followed) = lost {
+                    match send(&mut socket, &client, lost_message(followed)).await {
+                        Ok(()) => {}
+                        Err(e) if is_recoverable(&e) => debug!("Skipped lost notice: {e}"),
+                        Err(e) => {
+                            warn!("Got error while sending: {e}");
+
+                            break None;
+                        }
                     }
                 }
             }
-            Err(e) => {
-                eprintln!("Got error while checking for updates: {e}");
+            Some(Received::Lagged(dropped)) => {
+                warn!("Client lagged behind, {dropped} updates dropped");
+
+                match send(&mut socket, &client, lagged_message(client.format, dropped)).await {
+                    Ok(()) => {}
+                    Err(e) if is_recoverable(&e) => debug!("Skipped lag notice: {e}"),
+                    Err(e) => {
+                        warn!("Got error while sending: {e}");
+
+                        break None;
+                    }
+                }
+            }
+            None => {
+                warn!("Update channel closed");
 
-                break;
+                break close_frame(close_code::ERROR, "update channel closed");
             }
         }
+    };
+
+    if let Some(frame) = close {
+        if let Err(e) = send(&mut socket, &client, Message::Close(Some(frame))).await {
+            debug!("Got error while closing: {e}");
+        }
+    }
+
+    disconnected(&client);
+}
+
+/// Logs how long the client was connected and how much it got, for debugging flaky frontends
+fn disconnected(client: &Client) {
+    let stats = client.connection.client();
+
+    info!(
+        messages_sent = stats.messages_sent(),
+        uptime = ?stats.uptime(),
+        "Disconnected"
+    );
+}
+
+/// Sends the update encoded in the client's format, keeping track of aircraft it knows about.
+/// Updates that can't be represented in the format are skipped
+async fn send_update(
+    socket: &mut WebSocket,
+    client: &mut Client,
+    update: &Update,
+) -> Result<(), axum::Error> {
+    let is_new = match update {
+        Update::Position(point) => client.known.insert(point.mode_s.clone()),
+        Update::Expired(expired) => {
+            for mode_s in expired {
+                client.known.remove(mode_s);
+            }
+
+            false
+        }
+        Update::Emergency { .. } | Update::Ident(_) => false,
+    };
+
+    let text = |message: &TypedMessage| {
+        Message::Text(serde_json::to_string(message).expect("message serialization is infallible"))
+    };
+
+    let message = match client.format {
+        Format::Json => Message::Text(
+            serde_json::to_string(update).expect("update serialization is infallible"),
+        ),
+        Format::Typed => {
+            if let (true, Update::Position(point)) = (is_new, update) {
+                send(socket, client, text(&TypedMessage::New(&point.mode_s))).await?;
+            }
+
+            for message in TypedMessage::from_update(update) {
+                send(socket, client, text(&message)).await?;
+            }
+
+            return Ok(());
+        }
+        Format::Binary => match binary::encode(update) {
+            Some(message) => Message::Binary(message),
+            None => return Ok(()),
+        },
+    };
+
+    send(socket, client, message).await
+}
+
+/// Station info sent at connect: `{"type": "station", ...}` in the typed format,
+/// `{"station": {...}}` as a text message otherwise
+fn station_message(format: Format, station: Station) -> Message {
+    let text = match format {
+        Format::Typed => serde_json::to_string(&TypedMessage::Station(station)),
+        Format::Json | Format::Binary => {
+            serde_json::to_string(&HashMap::from([("station", station)]))
+        }
+    };
+
+    Message::Text(text.expect("station serialization is infallible"))
+}
+
+/// Notice for a client that fell behind its queue: `{"type": "lagged", "dropped": n}`
+/// in the typed format, `{"lagged": n}` as a text message otherwise
+fn lagged_message(format: Format, dropped: u64) -> Message {
+    let text = match format {
+        Format::Typed => serde_json::to_string(&TypedMessage::Lagged(dropped)),
+        Format::Json | Format::Binary => {
+            serde_json::to_string(&HashMap::from([("lagged", dropped)]))
+        }
+    };
+
+    Message::Text(text.expect("lag notice serialization is infallible"))
+}
+
+/// Notice for a client following an aircraft that it expired: `{"lost": hex}` as a text
+/// message. Typed clients get `{"type": "lost", ...}` as part of the expiration instead
+fn lost_message(mode_s: &SmolStr) -> Message {
+    Message::Text(
+        serde_json::to_string(&HashMap::from([("lost", mode_s)]))
+            .expect("lost notice serialization is infallible"),
+    )
+}
+
+/// Close frame with the given status code, so that clients can tell a planned shutdown
+/// (`1001`, going away) from a server error (`1011`) and back off accordingly
+fn close_frame(code: u16, reason: &'static str) -> Option<CloseFrame<'static>> {
+    Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    })
+}
+
+/// Whether the connection is still usable after the send error, so that only the message
+/// is lost, e.g. when the write buffer is momentarily full or the write was interrupted.
+/// Closed connections, protocol errors and send timeouts are fatal
+fn is_recoverable(error: &axum::Error) -> bool {
+    match error
+        .source()
+        .and_then(|source| source.downcast_ref::<tungstenite::Error>())
+    {
+        Some(tungstenite::Error::WriteBufferFull(_)) => true,
+        Some(tungstenite::Error::Io(e)) => matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+        ),
+        _ => false,
+    }
+}
+
+/// Sends the message, giving up after the client's send timeout
+async fn send(
+    socket: &mut WebSocket,
+    client: &Client,
+    message: Message,
+) -> Result<(), axum::Error> {
+    if client.send_timeout.is_zero() {
+        socket.send(message).await?;
+    } else {
+        tokio::time::timeout(client.send_timeout, socket.send(message))
+            .await
+            .map_err(axum::Error::new)??;
+    }
+
+    client.connection.client().message_sent();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::point::AircraftInfo;
+
+    use super::*;
+
+    /// Long enough for a ready update to be picked up
+    const PENDING_FOR: Duration = Duration::from_millis(50);
+
+    fn position(lat: f32, long: f32) -> Update {
+        Update::Position(Point::new(
+            "4CA2D1".into(),
+            (lat, long),
+            AircraftInfo::default(),
+        ))
+    }
+
+    fn invalid_positions() -> Vec<Update> {
+        vec![
+            position(0.0, 0.0),
+            position(f32::NAN, 10.0),
+            position(50.0, f32::INFINITY),
+            position(91.0, 10.0),
+        ]
+    }
+
+    /// Every frame a client could get for the update is a non-empty, well-formed message
+    fn assert_well_formed(update: &Update) {
+        let json: Value = serde_json::from_str(&serde_json::to_string(update).unwrap()).unwrap();
+        assert_eq!(json[0], "4CA2D1");
+        assert_eq!(json[1], serde_json::json!([53.5, -6.25]));
+
+        for message in TypedMessage::from_update(update) {
+            let json: Value =
+                serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+            assert!(json["type"].is_string(), "{json}");
+        }
+
+        assert!(binary::encode(update).is_some_and(|message| !message.is_empty()));
+    }
+
+    /// Latest-only subscription, along with the ends [`AppState::publish`] sends to
+    fn latest() -> (Sender<Option<Update>>, broadcast::Sender<Update>, Latest) {
+        // same as before the first record arrives from the source
+        let (sender, positions) = watch::channel(None);
+        let (events, events_receiver) = broadcast::channel(EVENT_QUEUE_CAPACITY);
+
+        let latest = Latest {
+            positions,
+            events: events_receiver,
+        };
+
+        (sender, events, latest)
+    }
+
+    #[tokio::test]
+    async fn latest_waits_for_first_valid_position() {
+        let (sender, _events, latest) = latest();
+        let mut updates = Updates::Latest(latest);
+
+        assert!(tokio::time::timeout(PENDING_FOR, updates.next())
+            .await
+            .is_err());
+
+        for update in invalid_positions() {
+            sender.send_replace(Some(update));
+            assert!(tokio::time::timeout(PENDING_FOR, updates.next())
+                .await
+                .is_err());
+        }
+
+        sender.send_replace(Some(position(53.5, -6.25)));
+        match updates.next().await {
+            Some(Received::Update(update)) => assert_well_formed(&update),
+            _ => panic!("expected the valid position"),
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_skips_invalid_positions() {
+        let (sender, receiver) = broadcast::channel(16);
+        let mut updates = Updates::Queued(receiver);
+
+        assert!(tokio::time::timeout(PENDING_FOR, updates.next())
+            .await
+            .is_err());
+
+        for update in invalid_positions() {
+            sender.send(update).unwrap();
+        }
+        sender.send(position(53.5, -6.25)).unwrap();
+
+        match updates.next().await {
+            Some(Received::Update(update)) => assert_well_formed(&update),
+            _ => panic!("expected the valid position"),
+        }
+    }
+
+    #[tokio::test]
+    async fn latest_keeps_events_superseded_by_positions() {
+        let (sender, events, mut latest) = latest();
+
+        events
+            .send(Update::Expired(vec!["4CA2D1".into(), "A061D9".into()]))
+            .unwrap();
+        sender.send_replace(Some(position(53.5, -6.25)));
+        sender.send_replace(Some(position(53.6, -6.25)));
+
+        match latest.next().await {
+            Some(Update::Expired(expired)) => assert_eq!(expired, ["4CA2D1", "A061D9"]),
+            update => panic!("expected the expiry, got {update:?}"),
+        }
+        match latest.next().await {
+            Some(Update::Position(point)) => assert_eq!(point.lat, 53.6),
+            update => panic!("expected the latest position, got {update:?}"),
+        }
+        assert!(tokio::time::timeout(PENDING_FOR, latest.next())
+            .await
+            .is_err());
     }
 
-    println!("Websocket context {who} destroyed");
+    #[tokio::test]
+    async fn latest_keeps_emergencies() {
+        let (sender, events, mut latest) = latest();
+
+        sender.send_replace(Some(position(53.5, -6.25)));
+        events
+            .send(Update::Emergency {
+                mode_s: "4CA2D1".into(),
+                squawk: "7700".into(),
+            })
+            .unwrap();
+        sender.send_replace(Some(position(53.6, -6.25)));
+
+        match latest.next().await {
+            Some(Update::Emergency { mode_s, squawk }) => {
+                assert_eq!((mode_s.as_str(), squawk.as_str()), ("4CA2D1", "7700"));
+            }
+            update => panic!("expected the emergency, got {update:?}"),
+        }
+        assert!(matches!(latest.next().await, Some(Update::Position(_))));
+    }
+
+    #[tokio::test]
+    async fn latest_keeps_idents_from_the_same_burst() {
+        let (sender, events, mut latest) = latest();
+
+        events.send(Update::Ident("4CA2D1".into())).unwrap();
+        sender.send_replace(Some(position(53.5, -6.25)));
+
+        assert!(matches!(latest.next().await, Some(Update::Ident(mode_s)) if mode_s == "4CA2D1"));
+        assert!(matches!(latest.next().await, Some(Update::Position(_))));
+    }
+
+    #[tokio::test]
+    async fn closed_channel_ends_updates() {
+        let (sender, _events, latest) = latest();
+        let mut updates = Updates::Latest(latest);
+        drop(sender);
+
+        assert!(updates.next().await.is_none());
+    }
 }
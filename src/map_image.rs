@@ -0,0 +1,398 @@
+//! Server-side rendering of current traffic to a PNG image, for status screens and bots.
+//!
+//! Positions are drawn with a plain equirectangular projection: latitude and longitude
+//! map linearly to the image axes, over a light graticule.
+//! Slippy map tiles use web mercator instead, and a transparent background, so that
+//! they can be overlaid on any standard tile layer.
+//! PNG is written by hand with uncompressed deflate blocks, which keeps it dependency-free
+
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{filter::BoundingBox, point::Point};
+
+pub const CONTENT_TYPE: &str = "image/png";
+
+pub const DEFAULT_WIDTH: u32 = 800;
+pub const DEFAULT_HEIGHT: u32 = 600;
+/// PNGs are stored uncompressed, so this keeps a single image at about 3 MB
+pub const MAX_SIZE: u32 = 1024;
+
+/// Margin around the traffic when bounds are fitted to it, as a fraction of its extent
+const FIT_MARGIN: f32 = 0.1;
+/// Fitted bounds never get narrower than that many degrees
+const FIT_MIN_SPAN: f32 = 0.5;
+const DOT_RADIUS: i64 = 3;
+
+/// Deepest zoom level tiles are served for, finer ones don't add anything for dots
+pub const MAX_ZOOM: u8 = 18;
+const TILE_SIZE: u32 = 256;
+/// Tiles are reused for that long, as traffic only moves a few pixels in between
+pub const TILE_TTL: Duration = Duration::from_secs(2);
+
+type Rgb = [u8; 3];
+
+const BACKGROUND: Rgb = [0x1b, 0x26, 0x33];
+const GRATICULE: Rgb = [0x2f, 0x3f, 0x52];
+const RECEIVER: Rgb = [0xff, 0xff, 0xff];
+const ON_GROUND: Rgb = [0x9e, 0x9e, 0x9e];
+const UNKNOWN_ALTITUDE: Rgb = [0xe0, 0xe0, 0xe0];
+
+/// Altitude color scale as (feet, color), interpolated in between
+const ALTITUDE_SCALE: [(f32, Rgb); 5] = [
+    (0.0, [0xff, 0x8c, 0x00]),
+    (5_000.0, [0xff, 0xd7, 0x00]),
+    (15_000.0, [0x32, 0xcd, 0x32]),
+    (30_000.0, [0x1e, 0x90, 0xff]),
+    (45_000.0, [0xda, 0x70, 0xd6]),
+];
+
+/// Renders the aircraft within bounds as dots colored by altitude, with the receiver
+/// location marked by a cross. Missing bounds are fitted to the traffic
+pub fn render(
+    points: &[Point],
+    bbox: BoundingBox,
+    receiver: Option<(f32, f32)>,
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let bounds = Bounds::fit(bbox, points, receiver);
+    let mut canvas = Canvas::new(width, height);
+
+    canvas.graticule(&bounds);
+
+    if let Some((lat, long)) = receiver {
+        let (x, y) = bounds.project(lat, long, width, height);
+        for offset in -4..=4 {
+            canvas.set(x + offset, y, RECEIVER);
+            canvas.set(x, y + offset, RECEIVER);
+        }
+    }
+
+    for point in points {
+        let (x, y) = bounds.project(point.lat, point.long, width, height);
+        canvas.dot(x, y, point_color(point));
+    }
+
+    canvas.into_png()
+}
+
+/// Renders the aircraft within the web mercator tile as dots colored by altitude,
+/// on a transparent background. Tile coordinates must be valid for the zoom level
+pub fn render_tile(points: &[Point], zoom: u8, x: u32, y: u32) -> Vec<u8> {
+    let mut canvas = Canvas::new(TILE_SIZE, TILE_SIZE);
+    let origin = (
+        f64::from(x) * f64::from(TILE_SIZE),
+        f64::from(y) * f64::from(TILE_SIZE),
+    );
+
+    for point in points {
+        let (px, py) = mercator(point.lat, point.long, zoom);
+        // dots of aircraft just outside the tile still reach into it
+        canvas.dot(
+            (px - origin.0).floor() as i64,
+            (py - origin.1).floor() as i64,
+            point_color(point),
+        );
+    }
+
+    canvas.into_png_keyed(BACKGROUND)
+}
+
+/// Global pixel coordinates of the position at the zoom level in web mercator.
+/// Latitudes beyond about 85° are clamped to the edge of the map
+fn mercator(lat: f32, long: f32, zoom: u8) -> (f64, f64) {
+    let size = f64::from(TILE_SIZE) * f64::from(1u32 << zoom);
+    let lat = f64::from(lat).to_radians();
+
+    let x = (f64::from(long) + 180.0) / 360.0 * size;
+    let y = (1.0 - lat.tan().asinh() / PI) / 2.0 * size;
+
+    (x, y.clamp(0.0, size))
+}
+
+/// Tile as (zoom, x, y)
+type TileKey = (u8, u32, u32);
+
+/// Rendered tiles with the time they were rendered, reused for [`TILE_TTL`]
+#[derive(Default)]
+pub struct TileCache {
+    tiles: Mutex<HashMap<TileKey, (Instant, Vec<u8>)>>,
+}
+
+impl TileCache {
+    /// Cached tile, if it's fresh enough
+    pub fn get(&self, key: TileKey) -> Option<Vec<u8>> {
+        self.lock()
+            .get(&key)
+            .filter(|(rendered_at, _)| rendered_at.elapsed() < TILE_TTL)
+            .map(|(_, png)| png.clone())
+    }
+
+    /// Caches a freshly rendered tile. Stale tiles are dropped along the way,
+    /// so the cache only holds recently requested ones
+    pub fn insert(&self, key: TileKey, png: Vec<u8>) {
+        let mut tiles = self.lock();
+        tiles.retain(|_, (rendered_at, _)| rendered_at.elapsed() < TILE_TTL);
+        tiles.insert(key, (Instant::now(), png));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<TileKey, (Instant, Vec<u8>)>> {
+        self.tiles.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn point_color(point: &Point) -> Rgb {
+    match (point.info.on_ground, point.info.altitude) {
+        (Some(true), _) => ON_GROUND,
+        (_, Some(altitude)) => altitude_color(altitude as f32),
+        (_, None) => UNKNOWN_ALTITUDE,
+    }
+}
+
+fn altitude_color(altitude: f32) -> Rgb {
+    let (first, last) = (ALTITUDE_SCALE[0], ALTITUDE_SCALE[ALTITUDE_SCALE.len() - 1]);
+    if altitude <= first.0 {
+        return first.1;
+    }
+
+    for pair in ALTITUDE_SCALE.windows(2) {
+        let ((low, low_color), (high, high_color)) = (pair[0], pair[1]);
+        if altitude <= high {
+            let t = (altitude - low) / (high - low);
+            let mix = |a: u8, b: u8| (f32::from(a) + (f32::from(b) - f32::from(a)) * t) as u8;
+
+            return [
+                mix(low_color[0], high_color[0]),
+                mix(low_color[1], high_color[1]),
+                mix(low_color[2], high_color[2]),
+            ];
+        }
+    }
+
+    last.1
+}
+
+struct Bounds {
+    min_lat: f32,
+    max_lat: f32,
+    min_long: f32,
+    max_long: f32,
+}
+
+impl Bounds {
+    /// Takes given bounds as is, filling in missing ones from the traffic and the receiver,
+    /// or from the whole world if there's nothing to fit to
+    fn fit(bbox: BoundingBox, points: &[Point], receiver: Option<(f32, f32)>) -> Self {
+        let positions: Vec<(f32, f32)> = points
+            .iter()
+            .map(|point| (point.lat, point.long))
+            .chain(receiver)
+            .collect();
+
+        let extent = |pick: fn(&(f32, f32)) -> f32, min: f32, max: f32| {
+            let (low, high) = positions
+                .iter()
+                .map(pick)
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), value| {
+                    (low.min(value), high.max(value))
+                });
+            if positions.is_empty() {
+                return (min, max);
+            }
+
+            let margin = ((high - low) * FIT_MARGIN).max(FIT_MIN_SPAN / 2.0);
+            ((low - margin).max(min), (high + margin).min(max))
+        };
+
+        let (min_lat, max_lat) = extent(|&(lat, _)| lat, -90.0, 90.0);
+        let (min_long, max_long) = extent(|&(_, long)| long, -180.0, 180.0);
+
+        Self {
+            min_lat: bbox.min_lat.unwrap_or(min_lat),
+            max_lat: bbox.max_lat.unwrap_or(max_lat),
+            min_long: bbox.min_long.unwrap_or(min_long),
+            max_long: bbox.max_long.unwrap_or(max_long),
+        }
+    }
+
+    /// Pixel coordinates of the position, possibly outside the image
+    fn project(&self, lat: f32, long: f32, width: u32, height: u32) -> (i64, i64) {
+        let x = (long - self.min_long) / (self.max_long - self.min_long) * width as f32;
+        let y = (self.max_lat - lat) / (self.max_lat - self.min_lat) * height as f32;
+
+        (x.floor() as i64, y.floor() as i64)
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![BACKGROUND; width as usize * height as usize],
+        }
+    }
+
+    /// Sets the pixel, ignoring ones outside the image
+    fn set(&mut self, x: i64, y: i64, color: Rgb) {
+        if (0..i64::from(self.width)).contains(&x) && (0..i64::from(self.height)).contains(&y) {
+            self.pixels[y as usize * self.width as usize + x as usize] = color;
+        }
+    }
+
+    fn dot(&mut self, x: i64, y: i64, color: Rgb) {
+        for dy in -DOT_RADIUS..=DOT_RADIUS {
+            for dx in -DOT_RADIUS..=DOT_RADIUS {
+                if dx * dx + dy * dy <= DOT_RADIUS * DOT_RADIUS {
+                    self.set(x + dx, y + dy, color);
+                }
+            }
+        }
+    }
+
+    /// Lines every 1, 5 or 10 degrees, depending on how much is shown
+    fn graticule(&mut self, bounds: &Bounds) {
+        let span = (bounds.max_lat - bounds.min_lat).max(bounds.max_long - bounds.min_long);
+        let step = match span {
+            span if span <= 10.0 => 1.0,
+            span if span <= 50.0 => 5.0,
+            _ => 10.0,
+        };
+
+        let (width, height) = (self.width, self.height);
+
+        // lines are counted in whole steps, as adding up floats stops advancing for large bounds
+        let lines = |min: f32, max: f32| {
+            ((min / step).ceil() as i64..=(max / step).floor() as i64)
+                .map(|line| line as f32 * step)
+        };
+
+        for lat in lines(bounds.min_lat, bounds.max_lat) {
+            let (_, y) = bounds.project(lat, bounds.min_long, width, height);
+            for x in 0..i64::from(width) {
+                self.set(x, y, GRATICULE);
+            }
+        }
+
+        for long in lines(bounds.min_long, bounds.max_long) {
+            let (x, _) = bounds.project(bounds.min_lat, long, width, height);
+            for y in 0..i64::from(height) {
+                self.set(x, y, GRATICULE);
+            }
+        }
+    }
+
+    fn into_png(self) -> Vec<u8> {
+        self.encode(None)
+    }
+
+    /// PNG with pixels of the given color transparent
+    fn into_png_keyed(self, transparent: Rgb) -> Vec<u8> {
+        self.encode(Some(transparent))
+    }
+
+    fn encode(self, transparent: Option<Rgb>) -> Vec<u8> {
+        // each scanline starts with its filter type, 0 meaning none
+        let mut raw = Vec::with_capacity(self.pixels.len() * 3 + self.height as usize);
+        for row in self.pixels.chunks(self.width as usize) {
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // 8 bits per channel, truecolor, default compression and filtering, no interlacing
+        header.extend([8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        if let Some(color) = transparent {
+            // truecolor transparency is a single 16-bit sample per channel
+            let key: Vec<u8> = color.iter().flat_map(|&c| [0, c]).collect();
+            chunk(&mut png, b"tRNS", &key);
+        }
+        chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+
+    png.extend(crc.to_be_bytes());
+}
+
+/// zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 11);
+    // deflate with 32K window, no preset dictionary
+    out.extend([0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(u8::from(last));
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+
+    out.extend(adler32(data).to_be_bytes());
+
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+
+    let (mut a, mut b) = (1, 0);
+    // sums stay within u32 for chunks of that size
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+
+    (b << 16) | a
+}
@@ -0,0 +1,400 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+/// SBS `MSG` rows come in transmission types 1 to 8
+const TRANSMISSION_TYPES: usize = 8;
+/// Records are counted per second over that many seconds, for the rolling message rate
+const RATE_WINDOW_SECS: usize = 60;
+
+/// Counters and gauges exposed in Prometheus text format on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// SBS records read from the source
+    records_read: AtomicU64,
+    /// SBS records that carried a valid position
+    positions_read: AtomicU64,
+    /// Records that couldn't be parsed
+    parse_failures: AtomicU64,
+    /// Records dropped for exceeding `--max-record-rate`
+    records_dropped: AtomicU64,
+    /// Positions dropped as out of range or bogus
+    positions_rejected: AtomicU64,
+    /// Currently open WebSocket connections
+    ws_connections: AtomicU64,
+    /// Open WebSocket connections by id, for `/stats`
+    ws_clients: Mutex<HashMap<u64, Arc<WsClient>>>,
+    /// Id of the next WebSocket connection
+    next_ws_client: AtomicU64,
+    /// SBS `MSG` rows by transmission type, starting with type 1
+    messages_by_type: [AtomicU64; TRANSMISSION_TYPES],
+    /// When the last record was read, in milliseconds since the Unix epoch. Zero if never
+    last_record_at: AtomicU64,
+    /// Records read in each of the recent seconds
+    records_per_second: RateWindow,
+}
+
+/// Ring of per-second record counts, indexed by Unix second
+#[derive(Debug)]
+struct RateWindow([RateBucket; RATE_WINDOW_SECS]);
+
+impl RateWindow {
+    fn bucket(&self, second: u64) -> &RateBucket {
+        &self.0[second as usize % RATE_WINDOW_SECS]
+    }
+}
+
+// `Default` isn't derived for arrays this long
+impl Default for RateWindow {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| RateBucket::default()))
+    }
+}
+
+/// Single WebSocket connection, for spotting clients that connected but stopped receiving
+#[derive(Debug)]
+pub struct WsClient {
+    addr: SocketAddr,
+    connected_at: Instant,
+    messages_sent: AtomicU64,
+}
+
+impl WsClient {
+    pub fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.connected_at.elapsed()
+    }
+}
+
+/// Number of records read within a single second
+#[derive(Debug, Default)]
+struct RateBucket {
+    /// Unix second the count belongs to, buckets of older seconds are stale
+    second: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_read(&self) {
+        let now = unix_millis(SystemTime::now());
+
+        self.records_read.fetch_add(1, Ordering::Relaxed);
+        self.last_record_at.store(now, Ordering::Relaxed);
+
+        let second = now / 1000;
+        let bucket = self.records_per_second.bucket(second);
+        // the first record of a second takes the bucket over from the one a window ago.
+        // a record racing with that may be lost, which is fine for a rate
+        if bucket.second.swap(second, Ordering::Relaxed) != second {
+            bucket.count.store(0, Ordering::Relaxed);
+        }
+        bucket.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records read per second during the last complete second,
+    /// and on average over the last [`RATE_WINDOW_SECS`] complete seconds
+    pub fn message_rates(&self) -> (f64, f64) {
+        let current = unix_millis(SystemTime::now()) / 1000;
+        let count = |second: u64| {
+            let bucket = self.records_per_second.bucket(second);
+            if bucket.second.load(Ordering::Relaxed) == second {
+                bucket.count.load(Ordering::Relaxed)
+            } else {
+                0
+            }
+        };
+
+        let last_second = count(current - 1);
+        let window: u64 = (1..=RATE_WINDOW_SECS as u64)
+            .map(|ago| count(current - ago))
+            .sum();
+
+        (last_second as f64, window as f64 / RATE_WINDOW_SECS as f64)
+    }
+
+    /// Time since the last record was read, `None` if nothing was read yet
+    pub fn since_last_record(&self) -> Option<Duration> {
+        let last_record_at = self.last_record_at.load(Ordering::Relaxed);
+        if last_record_at == 0 {
+            return None;
+        }
+
+        let elapsed = unix_millis(SystemTime::now()).saturating_sub(last_record_at);
+
+        Some(Duration::from_millis(elapsed))
+    }
+
+    /// Counts an SBS `MSG` row of the given transmission type, ignoring unknown types
+    pub fn sbs_message(&self, transmission_type: u8) {
+        if let Some(counter) = usize::from(transmission_type)
+            .checked_sub(1)
+            .and_then(|idx| self.messages_by_type.get(idx))
+        {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn position_read(&self) {
+        self.positions_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn parse_failure(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self) {
+        self.records_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn position_rejected(&self) {
+        self.positions_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a WebSocket connection from `addr` as open until the returned guard is dropped.
+    /// Returns `None` if `limit` connections are already open
+    pub fn ws_connection(
+        self: &Arc<Self>,
+        limit: u64,
+        addr: SocketAddr,
+    ) -> Option<WsConnectionGuard> {
+        self.ws_connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| {
+                (open < limit).then_some(open + 1)
+            })
+            .ok()?;
+
+        let id = self.next_ws_client.fetch_add(1, Ordering::Relaxed);
+        let client = Arc::new(WsClient {
+            addr,
+            connected_at: Instant::now(),
+            messages_sent: AtomicU64::new(0),
+        });
+        self.lock_ws_clients().insert(id, Arc::clone(&client));
+
+        Some(WsConnectionGuard {
+            metrics: Arc::clone(self),
+            id,
+            client,
+        })
+    }
+
+    fn lock_ws_clients(&self) -> MutexGuard<'_, HashMap<u64, Arc<WsClient>>> {
+        self.ws_clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Feed diagnostics since startup, for `/stats`, along with the open WebSocket connections.
+    /// Their addresses are only listed if `with_addrs` is set
+    pub fn stats(&self, with_addrs: bool) -> Stats {
+        let (rate_1s, rate_60s) = self.message_rates();
+
+        let mut ws_clients: Vec<WsClientStats> = self
+            .lock_ws_clients()
+            .values()
+            .map(|client| WsClientStats {
+                addr: with_addrs.then_some(client.addr),
+                uptime: client.uptime(),
+                messages_sent: client.messages_sent(),
+            })
+            .collect();
+        // longest connected first, so that the listing is stable between requests
+        ws_clients.sort_by_key(|client| Reverse(client.uptime));
+
+        Stats {
+            records_read: self.records_read.load(Ordering::Relaxed),
+            positions_read: self.positions_read.load(Ordering::Relaxed),
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+            records_dropped: self.records_dropped.load(Ordering::Relaxed),
+            rate_1s,
+            rate_60s,
+            messages_by_type: self
+                .messages_by_type
+                .each_ref()
+                .map(|counter| counter.load(Ordering::Relaxed)),
+            ws_clients,
+        }
+    }
+
+    /// Renders metrics in Prometheus text exposition format
+    pub fn render(&self, aircraft_tracked: usize) -> String {
+        let mut out = String::new();
+
+        let metrics = [
+            (
+                "planewatch_records_read_total",
+                "counter",
+                "SBS records read from the source",
+                self.records_read.load(Ordering::Relaxed),
+            ),
+            (
+                "planewatch_positions_read_total",
+                "counter",
+                "SBS records with a valid position",
+                self.positions_read.load(Ordering::Relaxed),
+            ),
+            (
+                "planewatch_parse_failures_total",
+                "counter",
+                "SBS records that failed to parse",
+                self.parse_failures.load(Ordering::Relaxed),
+            ),
+            (
+                "planewatch_records_dropped_total",
+                "counter",
+                "Records dropped for exceeding the rate limit",
+                self.records_dropped.load(Ordering::Relaxed),
+            ),
+            (
+                "planewatch_positions_rejected_total",
+                "counter",
+                "Positions dropped as out of range or (0, 0)",
+                self.positions_rejected.load(Ordering::Relaxed),
+            ),
+            (
+                "planewatch_aircraft_tracked",
+                "gauge",
+                "Aircraft currently tracked",
+                aircraft_tracked as u64,
+            ),
+            (
+                "planewatch_ws_connections",
+                "gauge",
+                "Open WebSocket connections",
+                self.ws_connections.load(Ordering::Relaxed),
+            ),
+        ];
+
+        for (name, kind, help, value) in metrics {
+            // writing to a String can't fail
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        let (rate_1s, rate_60s) = self.message_rates();
+        let rates = [
+            (
+                "planewatch_message_rate_1s",
+                "Records read per second during the last second",
+                rate_1s,
+            ),
+            (
+                "planewatch_message_rate_60s",
+                "Records read per second on average over the last minute",
+                rate_60s,
+            ),
+        ];
+
+        for (name, help, value) in rates {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        out
+    }
+}
+
+/// Counters for `/stats`, with SBS `MSG` rows counted by transmission type, and open
+/// WebSocket connections: `{"records_read": 10, ..., "transmission_types": {"1": 2, ...},
+/// "ws_clients": [{"addr": "192.0.2.1:50000", "connected_seconds": 12.5, "messages_sent": 40}]}`
+pub struct Stats {
+    records_read: u64,
+    positions_read: u64,
+    parse_failures: u64,
+    records_dropped: u64,
+    /// Records per second, see [`Metrics::message_rates`]
+    rate_1s: f64,
+    rate_60s: f64,
+    messages_by_type: [u64; TRANSMISSION_TYPES],
+    ws_clients: Vec<WsClientStats>,
+}
+
+/// Open WebSocket connection, with `null` for the address unless it's listed
+struct WsClientStats {
+    addr: Option<SocketAddr>,
+    uptime: Duration,
+    messages_sent: u64,
+}
+
+impl Serialize for WsClientStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("addr", &self.addr)?;
+        map.serialize_entry("connected_seconds", &self.uptime.as_secs_f64())?;
+        map.serialize_entry("messages_sent", &self.messages_sent)?;
+        map.end()
+    }
+}
+
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(8))?;
+        map.serialize_entry("records_read", &self.records_read)?;
+        map.serialize_entry("positions_read", &self.positions_read)?;
+        map.serialize_entry("parse_failures", &self.parse_failures)?;
+        map.serialize_entry("records_dropped", &self.records_dropped)?;
+        map.serialize_entry("rate_1s", &self.rate_1s)?;
+        map.serialize_entry("rate_60s", &self.rate_60s)?;
+        map.serialize_entry("transmission_types", &ByType(&self.messages_by_type))?;
+        map.serialize_entry("ws_clients", &self.ws_clients)?;
+        map.end()
+    }
+}
+
+struct ByType<'a>(&'a [u64; TRANSMISSION_TYPES]);
+
+impl Serialize for ByType<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(idx, count)| ((idx + 1).to_string(), count)),
+        )
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Keeps a WebSocket connection counted and listed while it's open
+pub struct WsConnectionGuard {
+    metrics: Arc<Metrics>,
+    id: u64,
+    client: Arc<WsClient>,
+}
+
+impl WsConnectionGuard {
+    pub fn client(&self) -> &WsClient {
+        &self.client
+    }
+}
+
+impl Drop for WsConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.lock_ws_clients().remove(&self.id);
+        self.metrics.ws_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,242 @@
+//! Publishing of live positions to an MQTT broker, e.g. for Home Assistant.
+//! Only the small part of MQTT 3.1.1 that's needed is implemented:
+//! connecting with optional credentials, QoS 0 publishing and keep-alive pings
+
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::broadcast::error::RecvError,
+    time::{sleep, timeout},
+};
+use tracing::{info, warn};
+
+use crate::{
+    point::{NamedPoint, Update},
+    shutdown_requested, AppState,
+};
+
+const DEFAULT_PORT: u16 = 1883;
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Broker disconnects clients that are silent for 1.5 times that
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const CONNACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBLISH_RETAIN: u8 = 0x01;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+const CLEAN_SESSION: u8 = 0x02;
+const HAS_PASSWORD: u8 = 0x40;
+const HAS_USERNAME: u8 = 0x80;
+
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    /// Broker address as `host:port`
+    pub broker: String,
+    /// Positions are published to `<prefix>/<hex>`
+    pub topic_prefix: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl MqttConfig {
+    /// Broker address from `mqtt://host[:port]` or plain `host[:port]`
+    pub fn broker_address(url: &str) -> String {
+        let address = url.strip_prefix("mqtt://").unwrap_or(url);
+        let address = address.trim_end_matches('/');
+
+        if address
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+        {
+            address.to_owned()
+        } else {
+            format!("{address}:{DEFAULT_PORT}")
+        }
+    }
+}
+
+/// Publishes live positions until the server shuts down, reconnecting with exponential backoff.
+/// Each position is retained, so that new subscribers get the latest state immediately,
+/// and retained messages of expired aircraft are cleared. Every update goes through the update
+/// queue, so expirations aren't superseded by positions; ones the publisher falls behind on
+/// are dropped and logged
+pub async fn run(config: MqttConfig, state: AppState) {
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+
+    loop {
+        match connect(&config).await {
+            Ok((reader, writer)) => {
+                info!("Connected to MQTT broker at {}", config.broker);
+                backoff = RECONNECT_BACKOFF_MIN;
+
+                match publish_updates(&config, &state, reader, writer).await {
+                    Ok(()) => break,
+                    Err(e) => warn!("Lost connection to MQTT broker: {e}"),
+                }
+            }
+            Err(e) => warn!("Failed to connect to MQTT broker at {}: {e}", config.broker),
+        }
+
+        tokio::select! {
+            () = sleep(backoff) => {}
+            () = shutdown_requested(state.shutdown.clone()) => break,
+        }
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+    }
+
+    info!("MQTT publisher stopped");
+}
+
+async fn connect(config: &MqttConfig) -> std::io::Result<(OwnedReadHalf, OwnedWriteHalf)> {
+    let stream = TcpStream::connect(&config.broker).await?;
+    let (mut reader, mut writer) = stream.into_split();
+
+    let mut flags = CLEAN_SESSION;
+    let mut payload = Vec::new();
+    put_string(&mut payload, &format!("planewatch-{}", std::process::id()));
+    if let Some(username) = &config.username {
+        flags |= HAS_USERNAME;
+        put_string(&mut payload, username);
+    }
+    if let Some(password) = &config.password {
+        flags |= HAS_PASSWORD;
+        put_string(&mut payload, password);
+    }
+
+    let mut body = Vec::new();
+    put_string(&mut body, "MQTT");
+    // protocol level of MQTT 3.1.1
+    body.push(4);
+    body.push(flags);
+    body.extend((KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    body.extend(payload);
+
+    writer.write_all(&packet(CONNECT, &body)).await?;
+
+    let mut connack = [0; 4];
+    timeout(CONNACK_TIMEOUT, reader.read_exact(&mut connack))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "no CONNACK"))??;
+
+    match connack {
+        [CONNACK, 2, _, 0] => Ok((reader, writer)),
+        [CONNACK, 2, _, code] => Err(std::io::Error::other(format!(
+            "broker refused connection with code {code}"
+        ))),
+        _ => Err(std::io::Error::other("unexpected reply to CONNECT")),
+    }
+}
+
+/// Publishes updates until the connection fails. Returns `Ok` on shutdown
+async fn publish_updates(
+    config: &MqttConfig,
+    state: &AppState,
+    mut reader: OwnedReadHalf,
+    mut writer: OwnedWriteHalf,
+) -> std::io::Result<()> {
+    let mut receiver = state.queue.subscribe();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    // replies (CONNACK aside) carry nothing of interest, but have to be read anyway
+    let mut incoming = [0; 256];
+
+    let shutdown = shutdown_requested(state.shutdown.clone());
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            update = receiver.recv() => {
+                let update = match update {
+                    Ok(update) => update,
+                    Err(RecvError::Lagged(dropped)) => {
+                        warn!("MQTT publisher fell behind, dropped {dropped} updates");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                match update {
+                    Update::Position(point) if !point.has_valid_position() => {}
+                    Update::Position(point) => {
+                        let payload = serde_json::to_vec(&NamedPoint(&point))
+                            .expect("point serialization is infallible");
+                        let topic = format!("{}/{}", config.topic_prefix, point.mode_s);
+
+                        writer.write_all(&publish(&topic, &payload)).await?;
+                    }
+                    Update::Expired(expired) => {
+                        for mode_s in expired {
+                            // empty retained message removes the retained one
+                            let topic = format!("{}/{mode_s}", config.topic_prefix);
+
+                            writer.write_all(&publish(&topic, &[])).await?;
+                        }
+                    }
+                    Update::Emergency { .. } | Update::Ident(_) => {}
+                }
+            }
+            _ = ping.tick() => writer.write_all(&packet(PINGREQ, &[])).await?,
+            read = reader.read(&mut incoming) => {
+                if read? == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            () = &mut shutdown => {
+                writer.write_all(&packet(DISCONNECT, &[])).await?;
+
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Retained QoS 0 PUBLISH packet
+fn publish(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(2 + topic.len() + payload.len());
+    put_string(&mut body, topic);
+    body.extend(payload);
+
+    packet(PUBLISH | PUBLISH_RETAIN, &body)
+}
+
+/// Packet with the fixed header: type and flags, then variable-length remaining length
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(5 + body.len());
+    out.push(kind);
+
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+
+        if len == 0 {
+            break;
+        }
+    }
+
+    out.extend(body);
+
+    out
+}
+
+/// UTF-8 string prefixed with its length
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    out.extend((value.len() as u16).to_be_bytes());
+    out.extend(value.as_bytes());
+}
@@ -0,0 +1,111 @@
+//! US civil registrations (N-numbers), which map to ICAO addresses `A00001` to `ADF7C7`
+//! in order, so they can be derived without an aircraft database.
+//!
+//! N-numbers are `N`, a digit 1–9, then up to 4 more digits, where the last one or two
+//! characters may instead be letters (no I and O), e.g. `N1`, `N1A`, `N12AB`, `N12345`
+
+const FIRST: u32 = 0xa0_0001;
+const LAST: u32 = 0xad_f7c7;
+
+const LETTERS: &[u8; 24] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// Number of registrations sharing the first digit, the first two, the first three
+/// and the first four digits
+const BUCKETS: [u32; 4] = [101_711, 10_111, 951, 35];
+/// Registrations with nothing after the digits, or with a 1 or 2 letter suffix
+const LETTER_SUFFIXES: u32 = 1 + LETTERS.len() as u32 * (LETTERS.len() as u32 + 1);
+
+/// N-number of the aircraft with the given hex ident, if it's in the US civil range
+pub fn n_number(mode_s: &str) -> Option<String> {
+    let icao = u32::from_str_radix(mode_s, 16).ok()?;
+    if !(FIRST..=LAST).contains(&icao) {
+        return None;
+    }
+
+    let mut offset = icao - FIRST;
+    let mut registration = String::from("N");
+
+    // first digit can't be 0
+    registration.push(digit(offset / BUCKETS[0] + 1));
+    offset %= BUCKETS[0];
+
+    for bucket in &BUCKETS[1..] {
+        if offset < LETTER_SUFFIXES {
+            push_letters(&mut registration, offset);
+            return Some(registration);
+        }
+
+        offset -= LETTER_SUFFIXES;
+        registration.push(digit(offset / bucket));
+        offset %= bucket;
+    }
+
+    // after four digits comes nothing, a single letter or the fifth digit
+    match offset {
+        0 => {}
+        offset if offset <= LETTERS.len() as u32 => {
+            registration.push(char::from(LETTERS[offset as usize - 1]));
+        }
+        offset => registration.push(digit(offset - LETTERS.len() as u32 - 1)),
+    }
+
+    Some(registration)
+}
+
+/// Appends the suffix at the given offset: 0 for none, then `A`, `AA` to `AZ`, `B`, `BA`, ...
+fn push_letters(registration: &mut String, offset: u32) {
+    if offset == 0 {
+        return;
+    }
+
+    let per_letter = LETTERS.len() as u32 + 1;
+    let (first, second) = ((offset - 1) / per_letter, (offset - 1) % per_letter);
+
+    registration.push(char::from(LETTERS[first as usize]));
+    if second > 0 {
+        registration.push(char::from(LETTERS[second as usize - 1]));
+    }
+}
+
+fn digit(value: u32) -> char {
+    char::from_digit(value, 10).expect("N-number digits are below 10")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_registrations() {
+        for (mode_s, registration) in [
+            ("A00001", "N1"),
+            ("A00002", "N1A"),
+            ("A00003", "N1AA"),
+            ("A0001A", "N1AZ"),
+            ("A0001B", "N1B"),
+            ("A061D9", "N12345"),
+            ("A00724", "N1000Z"),
+            ("A00725", "N10000"),
+            ("A4ADC0", "N400MM"),
+            ("AB8E4F", "N8437D"),
+            ("AC792E", "N90210"),
+            ("AD48FD", "N955UA"),
+            ("ADF7C7", "N99999"),
+        ] {
+            assert_eq!(n_number(mode_s).as_deref(), Some(registration), "{mode_s}");
+        }
+    }
+
+    #[test]
+    fn outside_us_block() {
+        for mode_s in ["A00000", "ADF7C8", "AE0000", "4CA2D1", "000000", "FFFFFF"] {
+            assert_eq!(n_number(mode_s), None, "{mode_s}");
+        }
+    }
+
+    #[test]
+    fn malformed_hex() {
+        assert_eq!(n_number(""), None);
+        assert_eq!(n_number("N12345"), None);
+    }
+}
@@ -0,0 +1,736 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{
+    ser::{SerializeMap, SerializeTuple},
+    Serialize, Serializer,
+};
+use smol_str::SmolStr;
+
+use crate::{country::country, geo::Polar, registry::AircraftMeta, station::Station};
+
+/// Aircraft info that is reported in separate messages from the position,
+/// and thus needs to be tracked per hex ident and merged into each point
+#[derive(Clone, Debug, Default)]
+pub struct AircraftInfo {
+    /// Barometric altitude in feet
+    pub altitude: Option<i32>,
+    /// Flight callsign, empty if none was seen yet
+    pub callsign: SmolStr,
+    /// Ground speed in knots
+    pub ground_speed: Option<f32>,
+    /// Track in degrees
+    pub track: Option<f32>,
+    /// Vertical rate in feet per minute, negative when descending
+    pub vertical_rate: Option<i32>,
+    /// Mode A transponder code, as 4 octal digits
+    pub squawk: Option<SmolStr>,
+    /// Whether the aircraft reports being on the ground
+    pub on_ground: Option<bool>,
+    /// Signal strength of the latest message in dBFS, if the source reports it
+    pub rssi: Option<f32>,
+    /// When the aircraft last asserted the special position identification (SPI) flag,
+    /// i.e. the pilot pressed "ident" when asked by ATC
+    pub ident_at: Option<SystemTime>,
+    /// Ground speed and track weren't reported, but estimated from consecutive positions
+    pub motion_derived: bool,
+    /// Position that the next estimate of ground speed and track starts from, and when it was seen
+    pub motion_base: Option<((f32, f32), SystemTime)>,
+    /// Details from the aircraft database, if it's configured and knows the aircraft
+    pub meta: Option<Arc<AircraftMeta>>,
+}
+
+/// Aircraft count as identing for that long after the SPI flag was last seen,
+/// as the flag itself is only momentary
+const IDENT_HOLD: Duration = Duration::from_secs(5);
+
+/// Positions closer in time than that aren't used to estimate speed,
+/// as their rounding would turn into absurd values
+const MIN_MOTION_INTERVAL: Duration = Duration::from_secs(2);
+/// Positions further apart than that aren't used either, the aircraft could've turned in between
+const MAX_MOTION_INTERVAL: Duration = Duration::from_secs(60);
+/// Estimates above that are position glitches rather than actual speed
+const MAX_DERIVED_SPEED_KNOTS: f64 = 1000.0;
+const KM_PER_NAUTICAL_MILE: f64 = 1.852;
+/// Aircraft that moved less than that keep their previous track, as the bearing would be noise
+const MIN_TRACK_DISTANCE_KM: f32 = 0.05;
+
+/// Partial aircraft information decoded from a single source message
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Mode S hex ident
+    pub mode_s: SmolStr,
+    /// (lat, long)
+    pub position: Option<(f32, f32)>,
+    pub altitude: Option<i32>,
+    pub callsign: Option<SmolStr>,
+    pub ground_speed: Option<f32>,
+    pub track: Option<f32>,
+    pub vertical_rate: Option<i32>,
+    pub squawk: Option<SmolStr>,
+    pub on_ground: Option<bool>,
+    /// Special position identification (SPI) flag
+    pub ident: Option<bool>,
+    /// Signal strength in dBFS
+    pub rssi: Option<f32>,
+}
+
+impl AircraftInfo {
+    /// Updates info with values present in the report, keeping previously known ones otherwise
+    pub fn merge(&mut self, report: &Report) {
+        // reported values replace the estimates altogether
+        if self.motion_derived && (report.ground_speed.is_some() || report.track.is_some()) {
+            self.ground_speed = None;
+            self.track = None;
+            self.motion_derived = false;
+        }
+
+        if let Some(callsign) = &report.callsign {
+            self.callsign = callsign.clone();
+        }
+        if report.altitude.is_some() {
+            self.altitude = report.altitude;
+        }
+        if report.ground_speed.is_some() {
+            self.ground_speed = report.ground_speed;
+        }
+        if report.track.is_some() {
+            self.track = report.track;
+        }
+        if report.vertical_rate.is_some() {
+            self.vertical_rate = report.vertical_rate;
+        }
+        if report.squawk.is_some() {
+            self.squawk = report.squawk.clone();
+        }
+        if report.on_ground.is_some() {
+            self.on_ground = report.on_ground;
+        }
+        if report.rssi.is_some() {
+            self.rssi = report.rssi;
+        }
+        if report.ident == Some(true) {
+            self.ident_at = Some(SystemTime::now());
+        }
+    }
+
+    /// Whether the SPI flag was asserted recently, see [`IDENT_HOLD`]
+    pub fn is_identing(&self) -> bool {
+        self.ident_at
+            .is_some_and(|ident_at| ident_at.elapsed().unwrap_or_default() <= IDENT_HOLD)
+    }
+
+    /// Estimates ground speed and track from the distance to the previous position, if the source
+    /// doesn't report them, e.g. for aircraft that only send Mode S rather than ADS-B
+    pub fn derive_motion(&mut self, position: (f32, f32), seen_at: SystemTime) {
+        let Some((base, base_at)) = self.motion_base else {
+            self.motion_base = Some((position, seen_at));
+            return;
+        };
+        let Ok(elapsed) = seen_at.duration_since(base_at) else {
+            self.motion_base = Some((position, seen_at));
+            return;
+        };
+        // the base is kept until enough time passes, so that frequent positions still add up
+        if elapsed < MIN_MOTION_INTERVAL {
+            return;
+        }
+        self.motion_base = Some((position, seen_at));
+
+        let reported =
+            !self.motion_derived && (self.ground_speed.is_some() || self.track.is_some());
+        if reported || elapsed > MAX_MOTION_INTERVAL {
+            return;
+        }
+
+        let polar = Polar::between(base, position);
+        let knots =
+            f64::from(polar.distance) / KM_PER_NAUTICAL_MILE / (elapsed.as_secs_f64() / 3600.0);
+        if knots > MAX_DERIVED_SPEED_KNOTS {
+            return;
+        }
+
+        self.ground_speed = Some(knots as f32);
+        if polar.distance >= MIN_TRACK_DISTANCE_KM {
+            self.track = Some(polar.bearing);
+        }
+        self.motion_derived = true;
+    }
+
+    /// Kind of emergency signalled by the squawk code, if any
+    pub fn emergency(&self) -> Option<&'static str> {
+        self.squawk.as_deref().and_then(emergency_kind)
+    }
+}
+
+/// Kind of emergency signalled by one of the well-known squawk codes
+pub fn emergency_kind(squawk: &str) -> Option<&'static str> {
+    match squawk {
+        "7500" => Some("hijack"),
+        "7600" => Some("radio_failure"),
+        "7700" => Some("general"),
+        _ => None,
+    }
+}
+
+/// Canonical form of a hex ident: trimmed and in upper case, as sources differ in both.
+/// Anything but 6 hex digits is rejected; a leading `~`, which dump1090 and readsb use
+/// to mark non-ICAO (e.g. TIS-B) addresses, is kept
+pub fn normalize_mode_s(hex: &str) -> Option<SmolStr> {
+    let hex = hex.trim();
+    let digits = hex.strip_prefix('~').unwrap_or(hex);
+
+    (digits.len() == 6 && digits.bytes().all(|digit| digit.is_ascii_hexdigit()))
+        .then(|| SmolStr::new(hex.to_ascii_uppercase()))
+}
+
+/// Altitude classification of an aircraft, so that clients don't have to duplicate thresholds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AltitudeBand {
+    Ground,
+    Low,
+    Mid,
+    High,
+    /// Airborne or unknown ground state, but no altitude reported yet
+    #[default]
+    Unknown,
+}
+
+impl AltitudeBand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AltitudeBand::Ground => "ground",
+            AltitudeBand::Low => "low",
+            AltitudeBand::Mid => "mid",
+            AltitudeBand::High => "high",
+            AltitudeBand::Unknown => "unknown",
+        }
+    }
+}
+
+impl Serialize for AltitudeBand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Thresholds between altitude bands in feet: below `low` is low,
+/// below `mid` is mid, and anything higher is high
+#[derive(Clone, Copy, Debug)]
+pub struct AltitudeBands {
+    pub low: i32,
+    pub mid: i32,
+}
+
+impl AltitudeBands {
+    pub fn classify(&self, info: &AircraftInfo) -> AltitudeBand {
+        if info.on_ground == Some(true) {
+            return AltitudeBand::Ground;
+        }
+
+        match info.altitude {
+            None => AltitudeBand::Unknown,
+            Some(altitude) if altitude < self.low => AltitudeBand::Low,
+            Some(altitude) if altitude < self.mid => AltitudeBand::Mid,
+            Some(_) => AltitudeBand::High,
+        }
+    }
+}
+
+/// Parses `low,mid`, e.g. `10000,30000`
+impl FromStr for AltitudeBands {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (low, mid) = s
+            .split_once(',')
+            .ok_or("expected two thresholds as \"low,mid\"")?;
+        let threshold = |value: &str| {
+            value
+                .trim()
+                .parse::<i32>()
+                .map_err(|e| format!("invalid threshold {value:?}: {e}"))
+        };
+
+        let bands = AltitudeBands {
+            low: threshold(low)?,
+            mid: threshold(mid)?,
+        };
+        if bands.low >= bands.mid {
+            return Err("low threshold must be below the mid one".to_owned());
+        }
+
+        Ok(bands)
+    }
+}
+
+/// Single aircraft position report, as stored in history and sent to clients
+#[derive(Clone, Debug)]
+pub struct Point {
+    /// Mode S hex ident
+    pub mode_s: SmolStr,
+    pub lat: f32,
+    pub long: f32,
+    pub info: AircraftInfo,
+    /// When the position was received
+    pub seen_at: SystemTime,
+    /// Position relative to the receiver, if its location is configured
+    pub polar: Option<Polar>,
+    pub altitude_band: AltitudeBand,
+}
+
+impl Point {
+    pub fn new(mode_s: SmolStr, (lat, long): (f32, f32), info: AircraftInfo) -> Self {
+        Self {
+            mode_s,
+            lat,
+            long,
+            info,
+            seen_at: SystemTime::now(),
+            polar: None,
+            altitude_band: AltitudeBand::Unknown,
+        }
+    }
+
+    /// Whether the coordinates could belong to a real aircraft. Besides being out of range,
+    /// exactly (0, 0) is rejected too: decoders emit it for unknown positions far more often
+    /// than an aircraft is right there, in the Gulf of Guinea.
+    /// Out-of-range check also rules out NaN and infinity, which can't be represented in JSON
+    pub fn has_valid_position(&self) -> bool {
+        (-90.0..=90.0).contains(&self.lat)
+            && (-180.0..=180.0).contains(&self.long)
+            && (self.lat, self.long) != (0.0, 0.0)
+    }
+}
+
+/// Serialized as
+/// `[mode_s, [lat, long], altitude, callsign, ground_speed, track, on_ground, last_seen]`,
+/// so that clients can keep destructuring the first two elements.
+/// Ground speed and track are derived from positions if the aircraft doesn't report them
+impl Serialize for Point {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(8)?;
+
+        tuple.serialize_element(&self.mode_s)?;
+        tuple.serialize_element(&(self.lat, self.long))?;
+        tuple.serialize_element(&self.info.altitude)?;
+        tuple.serialize_element(&self.info.callsign)?;
+        tuple.serialize_element(&self.info.ground_speed)?;
+        tuple.serialize_element(&self.info.track)?;
+        tuple.serialize_element(&self.info.on_ground)?;
+        // with the speed and track, lets clients dead-reckon the aircraft between updates
+        tuple.serialize_element(&unix_timestamp(self.seen_at))?;
+
+        tuple.end()
+    }
+}
+
+/// Update broadcasted to live clients
+#[derive(Clone, Debug)]
+pub enum Update {
+    /// New position report
+    Position(Point),
+    /// Hex idents of aircraft that weren't seen for too long and should be removed
+    Expired(Vec<SmolStr>),
+    /// Aircraft started squawking one of the emergency codes
+    Emergency { mode_s: SmolStr, squawk: SmolStr },
+    /// Aircraft started identing, see [`AircraftInfo::ident_at`]
+    Ident(SmolStr),
+}
+
+impl Update {
+    /// Whether it's a one-shot event that has to reach every client,
+    /// rather than something the next update supersedes
+    pub fn is_event(&self) -> bool {
+        matches!(
+            self,
+            Update::Expired(_) | Update::Emergency { .. } | Update::Ident(_)
+        )
+    }
+}
+
+/// Positions are serialized as a plain point, expirations as `{"expired": [mode_s, ...]}`,
+/// emergencies as `{"emergency": {"hex": mode_s, "squawk": "7700", "kind": "general"}}`,
+/// and idents as `{"ident": mode_s}`
+impl Serialize for Update {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Update::Position(point) => point.serialize(serializer),
+            Update::Expired(mode_s) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("expired", mode_s)?;
+                map.end()
+            }
+            Update::Emergency { mode_s, squawk } => {
+                struct Emergency<'a>(&'a SmolStr, &'a SmolStr);
+
+                impl Serialize for Emergency<'_> {
+                    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                        let mut map = serializer.serialize_map(Some(3))?;
+                        map.serialize_entry("hex", self.0)?;
+                        map.serialize_entry("squawk", self.1)?;
+                        map.serialize_entry("kind", &emergency_kind(self.1))?;
+                        map.end()
+                    }
+                }
+
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("emergency", &Emergency(mode_s, squawk))?;
+                map.end()
+            }
+            Update::Ident(mode_s) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("ident", mode_s)?;
+                map.end()
+            }
+        }
+    }
+}
+
+/// Live message in the structured format, where every message is an object tagged with `type`:
+/// `position` with the same fields as [`NamedPoint`], `new`, `lost` and `ident` with `hex`,
+/// `emergency` with `hex`, `squawk` and `kind`, `station` with the fields of [`Station`],
+/// and `lagged` with the number of updates `dropped`
+pub enum TypedMessage<'a> {
+    Position(&'a Point),
+    /// Aircraft that wasn't tracked within the expiry window showed up
+    New(&'a SmolStr),
+    /// Aircraft wasn't seen for too long
+    Lost(&'a SmolStr),
+    /// Aircraft started identing
+    Ident(&'a SmolStr),
+    Emergency {
+        mode_s: &'a SmolStr,
+        squawk: &'a SmolStr,
+    },
+    /// Sent once when a client connects, before any positions
+    Station(Station<'a>),
+    /// Client fell behind its update queue, and that many updates were dropped
+    Lagged(u64),
+}
+
+impl<'a> TypedMessage<'a> {
+    /// Messages representing the update, expirations are split into one per aircraft
+    pub fn from_update(update: &'a Update) -> Vec<Self> {
+        match update {
+            Update::Position(point) => vec![TypedMessage::Position(point)],
+            Update::Expired(expired) => expired.iter().map(TypedMessage::Lost).collect(),
+            Update::Emergency { mode_s, squawk } => {
+                vec![TypedMessage::Emergency { mode_s, squawk }]
+            }
+            Update::Ident(mode_s) => vec![TypedMessage::Ident(mode_s)],
+        }
+    }
+}
+
+impl Serialize for TypedMessage<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            TypedMessage::Position(point) => {
+                let mut map = serializer.serialize_map(Some(21 + meta_entries(point)))?;
+                map.serialize_entry("type", "position")?;
+                map.serialize_entry("hex", &point.mode_s)?;
+                serialize_state_entries(point, &mut map)?;
+                map.end()
+            }
+            TypedMessage::New(mode_s)
+            | TypedMessage::Lost(mode_s)
+            | TypedMessage::Ident(mode_s) => {
+                let kind = match self {
+                    TypedMessage::New(_) => "new",
+                    TypedMessage::Lost(_) => "lost",
+                    _ => "ident",
+                };
+
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("type", kind)?;
+                map.serialize_entry("hex", mode_s)?;
+                map.end()
+            }
+            TypedMessage::Emergency { mode_s, squawk } => {
+                let mut map = serializer.serialize_map(Some(4))?;
+                map.serialize_entry("type", "emergency")?;
+                map.serialize_entry("hex", mode_s)?;
+                map.serialize_entry("squawk", squawk)?;
+                map.serialize_entry("kind", &emergency_kind(squawk))?;
+                map.end()
+            }
+            TypedMessage::Station(station) => {
+                let mut map = serializer.serialize_map(Some(1 + Station::ENTRIES))?;
+                map.serialize_entry("type", "station")?;
+                station.serialize_entries(&mut map)?;
+                map.end()
+            }
+            TypedMessage::Lagged(dropped) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("type", "lagged")?;
+                map.serialize_entry("dropped", &dropped)?;
+                map.end()
+            }
+        }
+    }
+}
+
+/// Current state of tracked aircraft, serialized as
+/// `{"count": n, "aircraft": {mode_s: {lat, long, altitude, callsign, ...}}}`
+pub struct CurrentAircraft(pub Vec<Point>);
+
+impl Serialize for CurrentAircraft {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct ByModeS<'a>(&'a [Point]);
+
+        impl Serialize for ByModeS<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_map(
+                    self.0
+                        .iter()
+                        .map(|point| (&point.mode_s, AircraftState(point))),
+                )
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("count", &self.0.len())?;
+        map.serialize_entry("aircraft", &ByModeS(&self.0))?;
+        map.end()
+    }
+}
+
+/// Latest point of a single aircraft, serialized as an object with named fields
+struct AircraftState<'a>(&'a Point);
+
+impl Serialize for AircraftState<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(20 + meta_entries(self.0)))?;
+        serialize_state_entries(self.0, &mut map)?;
+        // age as of the response, so that clients can fade out stale aircraft
+        // without relying on their own clock
+        map.serialize_entry(
+            "seen_seconds",
+            &self.0.seen_at.elapsed().unwrap_or_default().as_secs_f32(),
+        )?;
+        map.end()
+    }
+}
+
+/// Point serialized as an object with named fields, including its hex ident
+pub struct NamedPoint<'a>(pub &'a Point);
+
+impl Serialize for NamedPoint<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(20 + meta_entries(self.0)))?;
+        map.serialize_entry("hex", &self.0.mode_s)?;
+        serialize_state_entries(self.0, &mut map)?;
+        map.end()
+    }
+}
+
+/// Aircraft closest to some location, nearest first, serialized as
+/// `{"count": n, "aircraft": [{hex, lat, long, ..., query_distance, query_bearing}]}`.
+/// `distance` and `bearing` stay relative to the receiver, the `query_` ones are from the location
+pub struct Nearest(pub Vec<(Point, Polar)>);
+
+impl Serialize for Nearest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Entry<'a>(&'a Point, Polar);
+
+        impl Serialize for Entry<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(22 + meta_entries(self.0)))?;
+                map.serialize_entry("hex", &self.0.mode_s)?;
+                serialize_state_entries(self.0, &mut map)?;
+                map.serialize_entry("query_distance", &self.1.distance)?;
+                map.serialize_entry("query_bearing", &self.1.bearing)?;
+                map.end()
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("count", &self.0.len())?;
+        map.serialize_entry(
+            "aircraft",
+            &self
+                .0
+                .iter()
+                .map(|(point, polar)| Entry(point, *polar))
+                .collect::<Vec<_>>(),
+        )?;
+        map.end()
+    }
+}
+
+/// Named fields of a point, shared by its object representations. Aircraft type from
+/// the database is `type_code` rather than `type`, which typed live messages are tagged with
+fn serialize_state_entries<M: SerializeMap>(point: &Point, map: &mut M) -> Result<(), M::Error> {
+    map.serialize_entry("lat", &point.lat)?;
+    map.serialize_entry("long", &point.long)?;
+    map.serialize_entry("altitude", &point.info.altitude)?;
+    map.serialize_entry("altitude_band", &point.altitude_band)?;
+    map.serialize_entry("callsign", &point.info.callsign)?;
+    map.serialize_entry("ground_speed", &point.info.ground_speed)?;
+    map.serialize_entry("track", &point.info.track)?;
+    map.serialize_entry("motion_derived", &point.info.motion_derived)?;
+    map.serialize_entry("vertical_rate", &point.info.vertical_rate)?;
+    map.serialize_entry("squawk", &point.info.squawk)?;
+    map.serialize_entry("emergency", &point.info.emergency())?;
+    map.serialize_entry("ident", &point.info.is_identing())?;
+    map.serialize_entry("on_ground", &point.info.on_ground)?;
+    map.serialize_entry("last_seen", &unix_timestamp(point.seen_at))?;
+    map.serialize_entry("rssi", &point.info.rssi)?;
+    map.serialize_entry("distance", &point.polar.map(|polar| polar.distance))?;
+    map.serialize_entry("bearing", &point.polar.map(|polar| polar.bearing))?;
+
+    let country = country(&point.mode_s);
+    map.serialize_entry("country", &country.map(|country| country.name))?;
+    map.serialize_entry("country_code", &country.map(|country| country.code))?;
+
+    // database details are omitted rather than null when unknown
+    if let Some(meta) = &point.info.meta {
+        if let Some(registration) = &meta.registration {
+            map.serialize_entry("registration", registration)?;
+        }
+        if let Some(type_code) = &meta.type_code {
+            map.serialize_entry("type_code", type_code)?;
+        }
+        if let Some(operator) = &meta.operator {
+            map.serialize_entry("operator", operator)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of entries [`serialize_state_entries`] writes from the aircraft database
+fn meta_entries(point: &Point) -> usize {
+    point.info.meta.as_ref().map_or(0, |meta| {
+        [
+            meta.registration.is_some(),
+            meta.type_code.is_some(),
+            meta.operator.is_some(),
+        ]
+        .into_iter()
+        .filter(|&present| present)
+        .count()
+    })
+}
+
+/// Single point of an aircraft's recent path, serialized as `[lat, long, timestamp]`
+#[derive(Clone, Copy, Debug)]
+pub struct TrackPoint {
+    pub lat: f32,
+    pub long: f32,
+    pub seen_at: SystemTime,
+}
+
+impl From<&Point> for TrackPoint {
+    fn from(point: &Point) -> Self {
+        Self {
+            lat: point.lat,
+            long: point.long,
+            seen_at: point.seen_at,
+        }
+    }
+}
+
+impl Serialize for TrackPoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.lat, self.long, unix_timestamp(self.seen_at)).serialize(serializer)
+    }
+}
+
+/// Seconds since Unix epoch, with sub-second precision
+pub fn unix_timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_hex_idents() {
+        for (hex, normalized) in [
+            ("4CA2D1", "4CA2D1"),
+            ("4ca2d1", "4CA2D1"),
+            ("4Ca2D1", "4CA2D1"),
+            (" 4ca2d1", "4CA2D1"),
+            ("4CA2D1  ", "4CA2D1"),
+            ("\t4ca2d1\r\n", "4CA2D1"),
+            ("~2a1b3c", "~2A1B3C"),
+            (" ~2A1B3C ", "~2A1B3C"),
+        ] {
+            assert_eq!(
+                normalize_mode_s(hex).as_deref(),
+                Some(normalized),
+                "{hex:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_hex_idents() {
+        for hex in [
+            "", "   ", "4CA2D", "4CA2D1A", "4C A2D1", "4CA2DG", "N12345", "~", "~4CA2D",
+            "~~4CA2D1", "0x4CA2D1", "+4CA2D",
+        ] {
+            assert_eq!(normalize_mode_s(hex), None, "{hex:?}");
+        }
+    }
+
+    #[test]
+    fn aircraft_type_is_type_code_everywhere() {
+        let mut point = Point::new(
+            "4CA2D1".into(),
+            (53.5, -6.25),
+            AircraftInfo {
+                meta: Some(Arc::new(AircraftMeta {
+                    registration: Some("EI-DVM".into()),
+                    type_code: Some("B738".into()),
+                    operator: None,
+                })),
+                ..Default::default()
+            },
+        );
+        point.polar = Some(Polar {
+            distance: 10.0,
+            bearing: 90.0,
+        });
+
+        let typed = serde_json::to_value(TypedMessage::Position(&point)).unwrap();
+        let named = serde_json::to_value(NamedPoint(&point)).unwrap();
+        let current = serde_json::to_value(CurrentAircraft(vec![point.clone()])).unwrap();
+        let nearest =
+            serde_json::to_value(Nearest(vec![(point.clone(), point.polar.unwrap())])).unwrap();
+
+        for aircraft in [
+            &typed,
+            &named,
+            &current["aircraft"]["4CA2D1"],
+            &nearest["aircraft"][0],
+        ] {
+            assert_eq!(aircraft["type_code"], "B738", "{aircraft}");
+            assert_eq!(aircraft["registration"], "EI-DVM", "{aircraft}");
+        }
+        assert_eq!(typed["type"], "position");
+        assert!(named.get("type").is_none());
+    }
+
+    #[test]
+    fn events() {
+        let point = Point::new("4CA2D1".into(), (53.5, -6.25), AircraftInfo::default());
+
+        assert!(!Update::Position(point).is_event());
+        assert!(Update::Expired(vec!["4CA2D1".into()]).is_event());
+        assert!(Update::Emergency {
+            mode_s: "4CA2D1".into(),
+            squawk: "7700".into(),
+        }
+        .is_event());
+        assert!(Update::Ident("4CA2D1".into()).is_event());
+    }
+}
@@ -0,0 +1,138 @@
+//! Recording of received positions to a CSV file, for later analysis or replay
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use csv::{StringRecord, Writer};
+use smol_str::SmolStr;
+
+use crate::point::{normalize_mode_s, unix_timestamp, Point, Report};
+
+/// Columns of the recording, in order
+pub const HEADER: [&str; 11] = [
+    "recorded_at",
+    "hex",
+    "lat",
+    "long",
+    "altitude",
+    "callsign",
+    "ground_speed",
+    "track",
+    "vertical_rate",
+    "squawk",
+    "on_ground",
+];
+
+/// Buffered records are written out at least that often, so that a crash loses little
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Output {
+    writer: Writer<File>,
+    flushed_at: Instant,
+}
+
+/// Appends positions to a CSV file
+#[derive(Debug)]
+pub struct Recorder {
+    output: Mutex<Output>,
+}
+
+impl Recorder {
+    /// Opens the file for appending, writing the header if it's empty
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+
+        let mut writer = Writer::from_writer(file);
+        if is_empty {
+            writer.write_record(HEADER)?;
+        }
+
+        Ok(Self {
+            output: Mutex::new(Output {
+                writer,
+                flushed_at: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn record(&self, point: &Point) -> io::Result<()> {
+        // at worst a panic leaves a partial row behind, which replay skips
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+
+        output.writer.write_record(row(point))?;
+
+        if output.flushed_at.elapsed() >= FLUSH_INTERVAL {
+            output.writer.flush()?;
+            output.flushed_at = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    /// Writes out buffered records, expected to be called every [`FLUSH_INTERVAL`]
+    /// and once the server shuts down
+    pub fn flush(&self) -> io::Result<()> {
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+
+        output.writer.flush()?;
+        output.flushed_at = Instant::now();
+
+        Ok(())
+    }
+}
+
+/// Point as a row of [`HEADER`] columns
+pub fn row(point: &Point) -> [String; HEADER.len()] {
+    let info = &point.info;
+
+    [
+        unix_timestamp(point.seen_at).to_string(),
+        point.mode_s.to_string(),
+        point.lat.to_string(),
+        point.long.to_string(),
+        optional(info.altitude),
+        info.callsign.to_string(),
+        optional(info.ground_speed),
+        optional(info.track),
+        optional(info.vertical_rate),
+        optional(info.squawk.as_ref()),
+        optional(info.on_ground.map(u8::from)),
+    ]
+}
+
+/// Reads a recorded row back as a report, along with its recording time in seconds since Unix epoch.
+/// Returns `None` for the header and malformed rows
+pub fn parse_record(record: &StringRecord) -> Option<(f64, Report)> {
+    let recorded_at = record.get(0)?.parse().ok()?;
+    let field = |idx| record.get(idx).filter(|field| !field.is_empty());
+    let parsed = |idx| field(idx).and_then(|field| field.parse().ok());
+
+    let report = Report {
+        mode_s: field(1).and_then(normalize_mode_s)?,
+        position: Some((parsed(2)?, parsed(3)?)),
+        altitude: field(4).and_then(|altitude| altitude.parse().ok()),
+        callsign: field(5).map(SmolStr::new),
+        ground_speed: parsed(6),
+        track: parsed(7),
+        vertical_rate: field(8).and_then(|rate| rate.parse().ok()),
+        squawk: field(9).map(SmolStr::new),
+        on_ground: field(10).map(|on_ground| on_ground == "1"),
+        // momentary, so it isn't recorded
+        ident: None,
+        rssi: None,
+    };
+
+    Some((recorded_at, report))
+}
+
+/// Missing values are recorded as empty fields
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
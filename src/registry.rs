@@ -0,0 +1,89 @@
+//! Aircraft metadata (registration, type, operator) looked up by hex ident
+//! in a CSV database, such as OpenSky's `aircraftDatabase.csv`
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use csv::{ReaderBuilder, StringRecord};
+use smol_str::SmolStr;
+
+/// Static details of a single aircraft
+#[derive(Debug, Default)]
+pub struct AircraftMeta {
+    pub registration: Option<SmolStr>,
+    /// ICAO type designator, or model name if the database has no designator
+    pub type_code: Option<SmolStr>,
+    pub operator: Option<SmolStr>,
+}
+
+/// Aircraft database, loaded into memory at startup so that lookups are cheap
+#[derive(Debug, Default)]
+pub struct Registry {
+    aircraft: HashMap<SmolStr, Arc<AircraftMeta>>,
+}
+
+impl Registry {
+    /// Loads the database from a CSV file with a header row. Columns are found by name:
+    /// `icao24` for the hex ident, `registration`, `typecode` (falling back to `model`)
+    /// and `operator` (falling back to `owner`)
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .from_path(path)
+            .map_err(|e| e.to_string())?;
+
+        let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+        let column = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| headers.iter().position(|header| header == *name))
+        };
+
+        let hex = column(&["icao24"]).ok_or("no icao24 column")?;
+        let registration = column(&["registration"]);
+        let type_code = column(&["typecode", "model"]);
+        let operator = column(&["operator", "owner"]);
+
+        let mut aircraft = HashMap::new();
+
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+
+            let Some(mode_s) = field(&record, Some(hex)) else {
+                continue;
+            };
+
+            let meta = AircraftMeta {
+                registration: field(&record, registration),
+                type_code: field(&record, type_code),
+                operator: field(&record, operator),
+            };
+
+            if meta.registration.is_none() && meta.type_code.is_none() && meta.operator.is_none() {
+                continue;
+            }
+
+            // sources report hex idents in upper case
+            aircraft.insert(SmolStr::new(mode_s.to_uppercase()), Arc::new(meta));
+        }
+
+        Ok(Self { aircraft })
+    }
+
+    /// Number of aircraft with known details
+    pub fn count(&self) -> usize {
+        self.aircraft.len()
+    }
+
+    pub fn lookup(&self, mode_s: &str) -> Option<Arc<AircraftMeta>> {
+        self.aircraft.get(mode_s).cloned()
+    }
+}
+
+/// Trimmed value of the column, empty ones are treated as absent
+fn field(record: &StringRecord, idx: Option<usize>) -> Option<SmolStr> {
+    record
+        .get(idx?)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(SmolStr::new)
+}
@@ -0,0 +1,234 @@
+//! SBS / BaseStation CSV format, as sent by dump1090 on port 30003
+
+use std::str::FromStr;
+
+use csv::StringRecord;
+use smol_str::SmolStr;
+
+use crate::point::{normalize_mode_s, Report};
+
+/// Extracts aircraft information from a single SBS record.
+/// Only `MSG` rows carry aircraft state; `ID` and `SEL` ones only a callsign,
+/// and the rest (`STA`, `AIR`, `CLK`) nothing of interest, so they're skipped
+pub fn parse_record(record: &StringRecord) -> Option<Report> {
+    // dump1090 pads callsigns with trailing spaces
+    let callsign = field(record, 10)
+        .map(str::trim_end)
+        .filter(|callsign| !callsign.is_empty())
+        .map(SmolStr::new);
+    // rows without a valid aircraft are of no use, even if dump1090 never sends them
+    let mode_s = field(record, 4).and_then(normalize_mode_s)?;
+
+    let transmission_type = match field(record, 0)? {
+        "MSG" => transmission_type(record)?,
+        "ID" | "SEL" => {
+            return Some(Report {
+                mode_s,
+                callsign,
+                ..Report::default()
+            })
+        }
+        _ => return None,
+    };
+
+    Some(Report {
+        mode_s,
+        // only surface (2) and airborne (3) position messages carry one
+        position: matches!(transmission_type, 2 | 3)
+            .then(|| parse_field(record, 14).zip(parse_field(record, 15)))
+            .flatten(),
+        altitude: parse_field(record, 11),
+        callsign,
+        ground_speed: parse_field(record, 12),
+        track: parse_field(record, 13),
+        vertical_rate: parse_field(record, 16),
+        squawk: field(record, 17)
+            .filter(|squawk| is_squawk(squawk))
+            .map(SmolStr::new),
+        ident: field(record, 20).and_then(parse_flag),
+        on_ground: field(record, 21).and_then(parse_flag),
+        // SBS doesn't carry signal strength
+        rssi: None,
+    })
+}
+
+/// Transmission type (1 to 8) of a `MSG` row, `None` for other rows
+pub fn transmission_type(record: &StringRecord) -> Option<u8> {
+    if field(record, 0)? != "MSG" {
+        return None;
+    }
+
+    parse_field(record, 1)
+}
+
+/// BaseStation flags are "0" when unset, and "1" or (in dump1090) "-1" when set
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "0" => Some(false),
+        "1" | "-1" => Some(true),
+        _ => None,
+    }
+}
+
+/// Whether the value is a valid Mode A code, i.e. exactly 4 octal digits
+pub fn is_squawk(value: &str) -> bool {
+    value.len() == 4 && value.bytes().all(|digit| matches!(digit, b'0'..=b'7'))
+}
+
+/// SBS field at given index. The reader is flexible, so short rows are expected, and
+/// dump1090 leaves fields that a message doesn't carry empty; both are treated as absent
+fn field(record: &StringRecord, idx: usize) -> Option<&str> {
+    record.get(idx).filter(|field| !field.is_empty())
+}
+
+/// Parses SBS field at given index, treating missing and malformed values as absent
+fn parse_field<T: FromStr>(record: &StringRecord, idx: usize) -> Option<T> {
+    field(record, idx).and_then(|field| field.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str) -> StringRecord {
+        StringRecord::from(line.split(',').collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn airborne_position() {
+        let report = parse_record(&record(
+            "MSG,3,1,1,4CA2D1,1,2023/10/10,12:00:00.000,2023/10/10,12:00:00.000,,35000,,,41.7,44.8,,,0,0,0,0",
+        ))
+        .unwrap();
+
+        assert_eq!(report.mode_s, "4CA2D1");
+        assert_eq!(report.position, Some((41.7, 44.8)));
+        assert_eq!(report.altitude, Some(35000));
+        assert_eq!(report.callsign, None);
+        assert_eq!(report.ident, Some(false));
+        assert_eq!(report.on_ground, Some(false));
+    }
+
+    #[test]
+    fn missing_fields_are_absent() {
+        let report = parse_record(&record(
+            "MSG,4,1,1,4CA2D1,1,2023/10/10,12:00:00.000,2023/10/10,12:00:00.000,,,451,234,,,-64,,,,,",
+        ))
+        .unwrap();
+
+        assert_eq!(report.position, None);
+        assert_eq!(report.altitude, None);
+        assert_eq!(report.ground_speed, Some(451.0));
+        assert_eq!(report.track, Some(234.0));
+        assert_eq!(report.vertical_rate, Some(-64));
+        assert_eq!(report.squawk, None);
+        assert_eq!(report.ident, None);
+        assert_eq!(report.on_ground, None);
+    }
+
+    #[test]
+    fn empty_or_partial_position() {
+        for line in [
+            "MSG,3,1,1,4CA2D1,1,,,,,,35000,,,,,,,0,0,0,0",
+            "MSG,3,1,1,4CA2D1,1,,,,,,35000,,,41.7,,,,0,0,0,0",
+            "MSG,3,1,1,4CA2D1,1,,,,,,35000,,,,44.8,,,0,0,0,0",
+            "MSG,3,1,1,4CA2D1,1,,,,,,35000,,,abc,44.8,,,0,0,0,0",
+        ] {
+            let report = parse_record(&record(line)).unwrap();
+
+            assert_eq!(report.position, None, "{line}");
+            assert_eq!(report.altitude, Some(35000), "{line}");
+        }
+    }
+
+    #[test]
+    fn position_only_from_position_messages() {
+        let report = parse_record(&record(
+            "MSG,5,1,1,4CA2D1,1,,,,,,35000,,,41.7,44.8,,,0,0,0,0",
+        ))
+        .unwrap();
+
+        assert_eq!(report.position, None);
+    }
+
+    #[test]
+    fn callsign_and_squawk() {
+        let report = parse_record(&record(
+            "MSG,1,1,1,4CA2D1,1,,,,,AFL123  ,,,,,,,7700,1,0,-1,1",
+        ))
+        .unwrap();
+
+        assert_eq!(report.callsign.as_deref(), Some("AFL123"));
+        assert_eq!(report.squawk.as_deref(), Some("7700"));
+        assert_eq!(report.ident, Some(true));
+        assert_eq!(report.on_ground, Some(true));
+
+        let report = parse_record(&record("MSG,6,1,1,4CA2D1,1,,,,,,,,,,,,7800,0,0,0,0")).unwrap();
+        assert_eq!(report.squawk, None);
+    }
+
+    #[test]
+    fn non_msg_rows() {
+        let report = parse_record(&record("ID,1,1,1,4CA2D1,1,,,,,AFL123")).unwrap();
+        assert_eq!(report.callsign.as_deref(), Some("AFL123"));
+        assert_eq!(report.position, None);
+
+        let report = parse_record(&record("SEL,1,1,1,4CA2D1,1,,,,,AFL123")).unwrap();
+        assert_eq!(report.callsign.as_deref(), Some("AFL123"));
+
+        for line in [
+            "STA,1,1,1,4CA2D1,1,,,,,PL",
+            "AIR,1,1,1,4CA2D1,1",
+            "CLK,1,1,1,4CA2D1,1",
+            "",
+        ] {
+            assert!(parse_record(&record(line)).is_none(), "{line}");
+            assert_eq!(transmission_type(&record(line)), None, "{line}");
+        }
+    }
+
+    #[test]
+    fn invalid_transmission_type() {
+        assert!(parse_record(&record("MSG,,1,1,4CA2D1,1,,,,,,35000")).is_none());
+        assert!(parse_record(&record("MSG,x,1,1,4CA2D1,1,,,,,,35000")).is_none());
+    }
+
+    #[test]
+    fn truncated_records() {
+        let full = "MSG,3,1,1,4CA2D1,1,2023/10/10,12:00:00.000,2023/10/10,12:00:00.000,AFL123,\
+                    35000,451,234,41.7,44.8,-64,1200,0,0,0,0";
+        let fields: Vec<&str> = full.split(',').collect();
+        assert_eq!(fields.len(), 22);
+
+        // every prefix of the row, down to the hex ident, parses without panicking
+        for len in 0..fields.len() {
+            let record = StringRecord::from(fields[..len].to_vec());
+            let report = parse_record(&record);
+
+            assert_eq!(report.is_some(), len > 4, "{len} fields");
+            assert_eq!(
+                transmission_type(&record),
+                (len > 1).then_some(3),
+                "{len} fields"
+            );
+
+            if let Some(report) = report {
+                assert_eq!(report.position.is_some(), len > 15, "{len} fields");
+                assert_eq!(report.altitude.is_some(), len > 11, "{len} fields");
+                assert_eq!(report.squawk.is_some(), len > 17, "{len} fields");
+                assert_eq!(report.on_ground.is_some(), len > 21, "{len} fields");
+            }
+        }
+
+        let report = parse_record(&StringRecord::from(fields)).unwrap();
+        assert_eq!(report.on_ground, Some(false));
+    }
+
+    #[test]
+    fn truncated_id_row() {
+        let report = parse_record(&record("ID,1,1,1,4CA2D1")).unwrap();
+
+        assert_eq!(report.mode_s, "4CA2D1");
+        assert_eq!(report.callsign, None);
+    }
+}
@@ -0,0 +1,84 @@
+//! Snapshots of the current aircraft state, so that the map isn't blank after a restart.
+//! Stored as CSV in the recording format, see [`recorder::HEADER`]
+
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use csv::{ReaderBuilder, Writer};
+
+use crate::{
+    point::{AircraftInfo, Point},
+    recorder,
+};
+
+/// Replaces the snapshot with the given points. It's written to a temporary file first,
+/// so that a crash in the middle leaves the previous snapshot intact
+pub fn save(path: &Path, points: &[Point]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    let mut writer = Writer::from_writer(File::create(&temporary)?);
+    writer.write_record(recorder::HEADER)?;
+    for point in points {
+        writer.write_record(recorder::row(point))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    fs::rename(&temporary, path)
+}
+
+/// Reads points from the snapshot, skipping ones older than `max_age`.
+/// A missing snapshot is treated as empty
+pub fn load(path: &Path, max_age: Duration) -> Result<Vec<Point>, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut points = Vec::new();
+
+    for record in ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(file)
+        .records()
+    {
+        let record = record.map_err(|e| e.to_string())?;
+
+        let Some((recorded_at, report)) = recorder::parse_record(&record) else {
+            continue;
+        };
+        let Some(position) = report.position else {
+            continue;
+        };
+        let Some(seen_at) = Duration::try_from_secs_f64(recorded_at)
+            .ok()
+            .map(|since_epoch| UNIX_EPOCH + since_epoch)
+        else {
+            continue;
+        };
+        if SystemTime::now()
+            .duration_since(seen_at)
+            .is_ok_and(|age| age > max_age)
+        {
+            continue;
+        }
+
+        let mut info = AircraftInfo::default();
+        info.merge(&report);
+
+        let mut point = Point::new(report.mode_s, position, info);
+        point.seen_at = seen_at;
+
+        points.push(point);
+    }
+
+    Ok(points)
+}
@@ -0,0 +1,703 @@
+//! Reading aircraft reports from the receiver (dump1090, readsb, etc.)
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read},
+    net::{SocketAddr, TcpStream},
+    ops::ControlFlow,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use csv::ReaderBuilder;
+use hyper::{
+    body::{self, Bytes},
+    client::HttpConnector,
+    Client, Uri,
+};
+use smol_str::SmolStr;
+use tracing::{error, info, warn};
+
+use crate::{
+    aircraft_json,
+    beast::{self, FrameReader},
+    geo::Polar,
+    n_number::n_number,
+    point::{AircraftInfo, Point, Report, Update},
+    recorder,
+    registry::AircraftMeta,
+    sbs, AppState,
+};
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Paced replays don't wait longer than that between records, e.g. when the receiver was off
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(10);
+/// How often aircraft that weren't heard from are forgotten
+const TRACKED_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Wire format of the source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// SBS / BaseStation CSV
+    Sbs,
+    /// BEAST binary frames
+    Beast,
+}
+
+impl Protocol {
+    /// Address that dump1090 serves this protocol on by default
+    pub fn default_source(self) -> &'static str {
+        match self {
+            Protocol::Sbs => "127.0.0.1:30003",
+            Protocol::Beast => "127.0.0.1:30005",
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sbs" => Ok(Protocol::Sbs),
+            "beast" => Ok(Protocol::Beast),
+            _ => Err(format!(
+                "unknown protocol {s:?}, expected \"sbs\" or \"beast\""
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Sbs => "sbs",
+            Protocol::Beast => "beast",
+        })
+    }
+}
+
+/// Where reports are read from
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// Live receiver feed
+    Tcp(SocketAddr, Protocol),
+    /// Live receiver feed from a Unix domain socket
+    Unix(PathBuf, Protocol),
+    /// Live receiver feed piped into the standard input, read until it ends
+    Stdin(Protocol),
+    /// `aircraft.json` served by readsb or dump1090 over plain HTTP, polled that often
+    AircraftJson(Uri, Duration),
+    /// File written by [`recorder::Recorder`]
+    Replay(PathBuf, ReplayOptions),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Tcp(addr, protocol) => write!(f, "{protocol} from {addr}"),
+            Source::Unix(path, protocol) => write!(f, "{protocol} from unix://{}", path.display()),
+            Source::Stdin(protocol) => write!(f, "{protocol} from stdin"),
+            Source::AircraftJson(url, interval) => {
+                write!(f, "{url} every {}ms", interval.as_millis())
+            }
+            Source::Replay(path, options) => write!(
+                f,
+                "replay of {} (pace: {}, loop: {})",
+                path.display(),
+                options.pace,
+                options.looped
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayOptions {
+    /// Whether to wait between records as long as between their recording, simulating a live feed.
+    /// Gaps longer than [`MAX_REPLAY_GAP`] are shortened to it
+    pub pace: bool,
+    /// Whether to start over once the file ends
+    pub looped: bool,
+}
+
+/// Reads reports from the source until the server shuts down
+pub fn run(source: Source, state: AppState) {
+    // aircraft restored from a snapshot start with what was known about them
+    let mut aircraft = Tracked::new(state.store.current_points(), state.store.expire_after());
+
+    match source {
+        Source::Tcp(addr, protocol) => read_live(
+            &addr.to_string(),
+            || TcpStream::connect(addr),
+            protocol,
+            &mut aircraft,
+            &state,
+        ),
+        Source::Unix(path, protocol) => read_live(
+            &path.display().to_string(),
+            || UnixStream::connect(&path),
+            protocol,
+            &mut aircraft,
+            &state,
+        ),
+        Source::Stdin(protocol) => {
+            info!("Reading {protocol} source from standard input");
+
+            if read_stream(io::stdin().lock(), protocol, &mut aircraft, &state).is_continue() {
+                info!("Standard input ended");
+            }
+        }
+        Source::AircraftJson(url, interval) => {
+            poll_aircraft_json(&url, interval, &mut aircraft, &state)
+        }
+        Source::Replay(path, options) => replay(&path, options, &mut aircraft, &state),
+    }
+
+    info!("Background task stopped");
+}
+
+/// Reads the live feed, reconnecting with exponential backoff whenever the connection drops
+fn read_live<S: Read>(
+    source_addr: &str,
+    connect: impl Fn() -> io::Result<S>,
+    protocol: Protocol,
+    aircraft: &mut Tracked,
+    state: &AppState,
+) {
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+
+    loop {
+        info!("Connecting to {protocol} source at {source_addr}");
+
+        match connect() {
+            Ok(stream) => {
+                info!("Connected to source at {source_addr}");
+                backoff = RECONNECT_BACKOFF_MIN;
+
+                if read_stream(stream, protocol, aircraft, state).is_break() {
+                    break;
+                }
+
+                warn!("Source stream ended");
+            }
+            Err(e) => {
+                warn!("Failed to connect to source at {source_addr}: {e}");
+            }
+        }
+
+        if is_shutting_down(state) {
+            break;
+        }
+
+        info!("Reconnecting in {}s", backoff.as_secs());
+        sleep(backoff);
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+    }
+}
+
+/// Polls `aircraft.json` until the server shuts down, feeding positions received since
+/// the previous poll, and other state as is
+fn poll_aircraft_json(url: &Uri, interval: Duration, aircraft: &mut Tracked, state: &AppState) {
+    // the source runs on a thread of its own, so requests are made on a runtime of its own too
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Failed to start HTTP client: {e}");
+
+            return;
+        }
+    };
+    let client = Client::new();
+    let mut positions_at = HashMap::new();
+
+    info!("Polling {url} every {}ms", interval.as_millis());
+
+    while !is_shutting_down(state) {
+        let started = Instant::now();
+
+        match runtime.block_on(fetch(&client, url)) {
+            Ok(body) => {
+                state.metrics.record_read();
+
+                match aircraft_json::parse(&body, &mut positions_at) {
+                    Ok(reports) => {
+                        for report in reports {
+                            apply_report(report, aircraft, state);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse {url}: {e}");
+                        state.metrics.parse_failure();
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to fetch {url}: {e}"),
+        }
+
+        sleep(interval.saturating_sub(started.elapsed()));
+    }
+}
+
+async fn fetch(client: &Client<HttpConnector>, url: &Uri) -> Result<Bytes, String> {
+    let response = tokio::time::timeout(FETCH_TIMEOUT, client.get(url.clone()))
+        .await
+        .map_err(|_| "timed out".to_owned())?
+        .map_err(|e| e.to_string())?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("server responded with {status}"));
+    }
+
+    body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Feeds a recording into the pipeline as if it was received live
+fn replay(path: &Path, options: ReplayOptions, aircraft: &mut Tracked, state: &AppState) {
+    loop {
+        info!("Replaying {}", path.display());
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Failed to open recording {}: {e}", path.display());
+
+                return;
+            }
+        };
+
+        let mut reader = ReaderBuilder::new().flexible(true).from_reader(file);
+        // recording time of the previous record and when it was due to be replayed
+        let mut paced: Option<(f64, Instant)> = None;
+
+        for record in reader.records() {
+            if is_shutting_down(state) {
+                return;
+            }
+
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    error!("Failed to read recording: {e}");
+                    state.metrics.parse_failure();
+
+                    if e.is_io_error() {
+                        return;
+                    }
+
+                    continue;
+                }
+            };
+
+            state.metrics.record_read();
+
+            let Some((recorded_at, report)) = recorder::parse_record(&record) else {
+                state.metrics.parse_failure();
+
+                continue;
+            };
+
+            if options.pace {
+                let now = Instant::now();
+                // due times add up rather than restart from now, so that replay doesn't drift
+                let due = match paced {
+                    Some((previous, previous_due)) => {
+                        previous_due + replay_gap(previous, recorded_at)
+                    }
+                    None => now,
+                };
+
+                sleep(due.saturating_duration_since(now));
+                paced = Some((recorded_at, due));
+            }
+
+            apply_report(report, aircraft, state);
+        }
+
+        if !options.looped || is_shutting_down(state) {
+            break;
+        }
+    }
+
+    info!("Recording ended");
+}
+
+/// Time to wait between replaying records recorded at those times. Timestamps going backwards
+/// don't wait at all, and gaps between recording sessions are cut short
+fn replay_gap(previous: f64, recorded_at: f64) -> Duration {
+    Duration::try_from_secs_f64(recorded_at - previous)
+        .unwrap_or_default()
+        .min(MAX_REPLAY_GAP)
+}
+
+/// Reads the stream in the given protocol until it ends, breaking on shutdown
+fn read_stream(
+    stream: impl Read,
+    protocol: Protocol,
+    aircraft: &mut Tracked,
+    state: &AppState,
+) -> ControlFlow<()> {
+    let mut limit = RateLimit::new(state.max_record_rate);
+
+    match protocol {
+        Protocol::Sbs => read_sbs(stream, aircraft, &mut limit, state),
+        Protocol::Beast => read_beast(stream, aircraft, &mut limit, state),
+    }
+}
+
+/// Caps how many records are processed per second, counted in fixed one-second windows,
+/// so that a flooding source can't pin the CPU. Records over the cap are dropped
+struct RateLimit {
+    max: Option<u32>,
+    window_start: Instant,
+    processed: u32,
+    dropped: u32,
+}
+
+impl RateLimit {
+    fn new(max: Option<u32>) -> Self {
+        Self {
+            max,
+            window_start: Instant::now(),
+            processed: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Whether another record fits into the current window
+    fn allow(&mut self) -> bool {
+        let Some(max) = self.max else {
+            return true;
+        };
+
+        if self.window_start.elapsed() >= RATE_LIMIT_WINDOW {
+            if self.dropped > 0 {
+                warn!("Dropped {} records over the limit of {max}/s", self.dropped);
+            }
+
+            self.window_start = Instant::now();
+            self.processed = 0;
+            self.dropped = 0;
+        }
+
+        if self.processed < max {
+            self.processed += 1;
+
+            true
+        } else {
+            self.dropped += 1;
+
+            false
+        }
+    }
+}
+
+/// Reads SBS records until the stream ends, breaking on shutdown
+fn read_sbs(
+    stream: impl Read,
+    aircraft: &mut Tracked,
+    limit: &mut RateLimit,
+    state: &AppState,
+) -> ControlFlow<()> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(stream);
+
+    for record in reader.records() {
+        if is_shutting_down(state) {
+            return ControlFlow::Break(());
+        }
+
+        match record {
+            Ok(record) => {
+                state.metrics.record_read();
+                if !limit.allow() {
+                    state.metrics.record_dropped();
+
+                    continue;
+                }
+
+                if let Some(transmission_type) = sbs::transmission_type(&record) {
+                    state.metrics.sbs_message(transmission_type);
+                }
+
+                if let Some(report) = sbs::parse_record(&record) {
+                    apply_report(report, aircraft, state);
+                }
+            }
+            Err(e) if e.is_io_error() => {
+                warn!("Lost connection to source: {e}");
+
+                break;
+            }
+            Err(e) => {
+                warn!("Failed to parse source record: {e}");
+                state.metrics.parse_failure();
+            }
+        }
+    }
+
+    ControlFlow::Continue(())
+}
+
+/// Reads BEAST frames until the stream ends, breaking on shutdown
+fn read_beast(
+    stream: impl Read,
+    aircraft: &mut Tracked,
+    limit: &mut RateLimit,
+    state: &AppState,
+) -> ControlFlow<()> {
+    let mut reader = FrameReader::new(stream);
+    let mut decoder = beast::Decoder::default();
+
+    loop {
+        if is_shutting_down(state) {
+            return ControlFlow::Break(());
+        }
+
+        let message = match reader.next_message() {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Lost connection to source: {e}");
+
+                break;
+            }
+        };
+
+        state.metrics.record_read();
+        if !limit.allow() {
+            state.metrics.record_dropped();
+
+            continue;
+        }
+
+        match decoder.decode(&message) {
+            Ok(Some(report)) => apply_report(report, aircraft, state),
+            Ok(None) => {}
+            Err(beast::BadCrc) => state.metrics.parse_failure(),
+        }
+    }
+
+    ControlFlow::Continue(())
+}
+
+/// Puts points from a state snapshot back into the store, enriched the same way as live ones
+pub fn restore(points: Vec<Point>, state: &AppState) {
+    for mut point in points {
+        point.info.meta = aircraft_meta(&point.mode_s, state);
+        point.polar = state
+            .receiver
+            .map(|receiver| Polar::between(receiver, (point.lat, point.long)));
+        point.altitude_band = state.altitude_bands.classify(&point.info);
+
+        state.store.insert(point);
+    }
+}
+
+/// Details from the aircraft database, with the registration derived from the hex ident
+/// for US aircraft whose registration the database doesn't know
+fn aircraft_meta(mode_s: &str, state: &AppState) -> Option<Arc<AircraftMeta>> {
+    let meta = state
+        .registry
+        .as_ref()
+        .and_then(|registry| registry.lookup(mode_s));
+    if meta
+        .as_ref()
+        .is_some_and(|meta| meta.registration.is_some())
+    {
+        return meta;
+    }
+
+    let Some(registration) = n_number(mode_s) else {
+        return meta;
+    };
+    let meta = meta.as_deref();
+
+    Some(Arc::new(AircraftMeta {
+        registration: Some(SmolStr::new(registration)),
+        type_code: meta.and_then(|meta| meta.type_code.clone()),
+        operator: meta.and_then(|meta| meta.operator.clone()),
+    }))
+}
+
+fn is_shutting_down(state: &AppState) -> bool {
+    *state.shutdown.borrow()
+}
+
+/// Info of each aircraft merged from its reports. Callsign, altitude, speed and track
+/// are sent in different messages, mostly separately from position, so last seen values
+/// are remembered for each hex ident until the aircraft expires
+struct Tracked {
+    aircraft: HashMap<SmolStr, (AircraftInfo, Instant)>,
+    expire_after: Duration,
+    swept_at: Instant,
+}
+
+impl Tracked {
+    fn new(points: Vec<Point>, expire_after: Duration) -> Self {
+        let now = Instant::now();
+
+        Self {
+            aircraft: points
+                .into_iter()
+                .map(|point| (point.mode_s, (point.info, now)))
+                .collect(),
+            expire_after,
+            swept_at: now,
+        }
+    }
+
+    /// Info of the aircraft that a report was just received from
+    fn heard(&mut self, mode_s: &SmolStr, state: &AppState) -> &mut AircraftInfo {
+        let now = Instant::now();
+        self.sweep(now);
+
+        let (info, heard_at) = self.aircraft.entry(mode_s.clone()).or_insert_with(|| {
+            let info = AircraftInfo {
+                meta: aircraft_meta(mode_s, state),
+                ..AircraftInfo::default()
+            };
+
+            (info, now)
+        });
+        *heard_at = now;
+
+        info
+    }
+
+    /// Forgets aircraft that weren't heard from for `expire_after`.
+    /// Only done at most every [`TRACKED_SWEEP_INTERVAL`], rather than on each report
+    fn sweep(&mut self, now: Instant) {
+        if now.duration_since(self.swept_at) < TRACKED_SWEEP_INTERVAL {
+            return;
+        }
+
+        let expire_after = self.expire_after;
+        self.aircraft
+            .retain(|_, (_, heard_at)| now.duration_since(*heard_at) <= expire_after);
+        self.swept_at = now;
+    }
+}
+
+/// Merges single report into per-aircraft info,
+/// storing and broadcasting a point if the report carries a position
+fn apply_report(report: Report, aircraft: &mut Tracked, state: &AppState) {
+    let info = aircraft.heard(&report.mode_s, state);
+    let previous_squawk = info.squawk.clone();
+    let was_identing = info.is_identing();
+    info.merge(&report);
+
+    // the flag is repeated while it lasts, so only its start is announced
+    if !was_identing && info.is_identing() {
+        info!("{} is identing", report.mode_s);
+
+        state.publish(Update::Ident(report.mode_s.clone()));
+    }
+
+    // squawk is repeated in many messages, so only its change is announced
+    if info.squawk != previous_squawk && info.emergency().is_some() {
+        if let Some(squawk) = &info.squawk {
+            warn!("{} is squawking {squawk}", report.mode_s);
+
+            state.publish(Update::Emergency {
+                mode_s: report.mode_s.clone(),
+                squawk: squawk.clone(),
+            });
+        }
+    }
+
+    let Some(position) = report.position else {
+        return;
+    };
+
+    let mut point = Point::new(report.mode_s, position, info.clone());
+
+    if !point.has_valid_position() {
+        state.metrics.position_rejected();
+
+        return;
+    }
+
+    info.derive_motion(position, point.seen_at);
+    point.info.clone_from(info);
+
+    state.metrics.position_read();
+
+    point.polar = state
+        .receiver
+        .map(|receiver| Polar::between(receiver, position));
+    point.altitude_band = state.altitude_bands.classify(&point.info);
+
+    if state.store.update_if_repeated(&point) {
+        return;
+    }
+
+    state.coverage.record(&point);
+
+    if let Some(recorder) = &state.recorder {
+        if let Err(e) = recorder.record(&point) {
+            error!("Failed to record position: {e}");
+        }
+    }
+
+    state.store.insert(point.clone());
+
+    state.publish(Update::Position(point));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_aircraft_not_heard_from() {
+        let start = Instant::now();
+        let points = ["4CA2D1", "A061D9"]
+            .map(|hex| Point::new(hex.into(), (53.5, -6.25), AircraftInfo::default()));
+        let mut tracked = Tracked::new(points.into(), Duration::from_secs(60));
+        tracked.aircraft.get_mut("A061D9").unwrap().1 = start + Duration::from_secs(30);
+
+        tracked.sweep(start + Duration::from_secs(88));
+        assert!(tracked.aircraft.contains_key("A061D9"));
+        assert!(!tracked.aircraft.contains_key("4CA2D1"));
+
+        // sweeps aren't done more often than the interval
+        tracked.sweep(start + Duration::from_secs(91));
+        assert!(tracked.aircraft.contains_key("A061D9"));
+
+        tracked.sweep(start + Duration::from_secs(88) + TRACKED_SWEEP_INTERVAL);
+        assert!(tracked.aircraft.is_empty());
+    }
+
+    #[test]
+    fn replay_gaps() {
+        let start = 1_700_000_000.0;
+
+        assert_eq!(replay_gap(start, start), Duration::ZERO);
+        assert_eq!(replay_gap(start, start + 0.25), Duration::from_millis(250));
+        assert_eq!(replay_gap(start, start + 10.0), MAX_REPLAY_GAP);
+        // next recording session, days later
+        assert_eq!(replay_gap(start, start + 86400.0 * 3.0), MAX_REPLAY_GAP);
+        // clock stepped back
+        assert_eq!(replay_gap(start, start - 0.5), Duration::ZERO);
+        assert_eq!(replay_gap(start, start - 3600.0), Duration::ZERO);
+        assert_eq!(replay_gap(start, f64::NAN), Duration::ZERO);
+    }
+}
@@ -0,0 +1,69 @@
+//! Information about the receiver itself, so that generic frontends can center the map
+//! and draw range rings without hardcoding the antenna location
+
+use std::time::SystemTime;
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::point::unix_timestamp;
+
+/// Version of the server, reported to clients
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Git commit the server was built from, `unknown` if it wasn't built from a checkout
+pub const COMMIT: &str = env!("PLANEWATCH_GIT_COMMIT");
+/// When the server was built, in seconds since the Unix epoch
+pub const BUILT_AT: &str = env!("PLANEWATCH_BUILT_AT");
+
+/// Build of the running server, serialized as `{"version": ..., "commit": ..., "built_at": ...}`
+pub struct Build;
+
+impl Build {
+    const ENTRIES: usize = 3;
+
+    fn serialize_entries<M: SerializeMap>(map: &mut M) -> Result<(), M::Error> {
+        map.serialize_entry("version", VERSION)?;
+        map.serialize_entry("commit", COMMIT)?;
+        // timestamps are numbers everywhere else in the API
+        map.serialize_entry("built_at", &BUILT_AT.parse::<u64>().ok())
+    }
+}
+
+impl Serialize for Build {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(Self::ENTRIES))?;
+        Self::serialize_entries(&mut map)?;
+        map.end()
+    }
+}
+
+/// Serialized as `{"name": ..., "lat": ..., "long": ..., "server_time": ...}` followed by
+/// the [`Build`] fields, with `null` for whatever isn't configured. Server time lets clients
+/// correct for their clock when telling how stale a position is from its `last_seen`
+#[derive(Clone, Copy, Debug)]
+pub struct Station<'a> {
+    /// Display name of the receiver
+    pub name: Option<&'a str>,
+    /// Receiver location as (lat, long)
+    pub location: Option<(f32, f32)>,
+}
+
+impl Station<'_> {
+    pub const ENTRIES: usize = 4 + Build::ENTRIES;
+
+    /// Writes the fields into an already started map, to share them between message formats
+    pub fn serialize_entries<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        map.serialize_entry("name", &self.name)?;
+        map.serialize_entry("lat", &self.location.map(|(lat, _)| lat))?;
+        map.serialize_entry("long", &self.location.map(|(_, long)| long))?;
+        map.serialize_entry("server_time", &unix_timestamp(SystemTime::now()))?;
+        Build::serialize_entries(map)
+    }
+}
+
+impl Serialize for Station<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(Self::ENTRIES))?;
+        self.serialize_entries(&mut map)?;
+        map.end()
+    }
+}
@@ -0,0 +1,360 @@
+//! Storage of received points, split so that ingestion and reads don't contend on a single lock:
+//! per-aircraft state is sharded by hex ident, and global history is kept in immutable chunks
+//! that readers can grab without copying.
+//!
+//! Every update leaves the state consistent, so poisoned locks are taken over rather than
+//! turning a single panic into a failure of every following request
+
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    hash::BuildHasher,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Serialize, Serializer};
+use smol_str::SmolStr;
+
+use crate::point::{Point, TrackPoint};
+
+/// Number of shards per-aircraft state is split into
+const SHARDS: usize = 16;
+
+/// Number of points in each sealed chunk of history
+const HISTORY_CHUNK: usize = 1024;
+
+/// State of a single aircraft
+#[derive(Debug)]
+struct Aircraft {
+    latest: Point,
+    /// Recent positions, oldest first
+    track: VecDeque<TrackPoint>,
+}
+
+/// Global history of all received points, oldest first
+#[derive(Debug, Default)]
+struct History {
+    sealed: VecDeque<Arc<[Point]>>,
+    current: Vec<Point>,
+    /// Points at the start of the oldest sealed chunk that are already past the limit
+    skipped: usize,
+}
+
+impl History {
+    fn len(&self) -> usize {
+        self.sealed.len() * HISTORY_CHUNK - self.skipped + self.current.len()
+    }
+
+    fn pop_chunk(&mut self) {
+        self.sealed.pop_front();
+        self.skipped = 0;
+    }
+}
+
+/// Points history at some moment, serialized as a single array
+pub struct HistorySnapshot {
+    sealed: Vec<Arc<[Point]>>,
+    current: Vec<Point>,
+    /// Number of points at the start that are past the limit
+    skipped: usize,
+    /// Points received before that are left out, as chunks are only dropped as a whole
+    cutoff: Option<SystemTime>,
+}
+
+impl HistorySnapshot {
+    /// Points in the snapshot, oldest first
+    pub fn points(&self) -> impl Iterator<Item = &Point> {
+        self.sealed
+            .iter()
+            .flat_map(|chunk| chunk.iter())
+            .chain(&self.current)
+            .skip(self.skipped)
+            .filter(|point| self.cutoff.is_none_or(|cutoff| point.seen_at >= cutoff))
+    }
+}
+
+impl Serialize for HistorySnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.points())
+    }
+}
+
+#[derive(Debug)]
+pub struct Store {
+    shards: Vec<RwLock<HashMap<SmolStr, Aircraft>>>,
+    hasher: RandomState,
+    history: Mutex<History>,
+    /// Max number of points kept in history, even if they're within retention
+    history_limit: usize,
+    /// Points older than that are dropped from history
+    history_retention: Option<Duration>,
+    /// Max number of points kept in each track
+    track_length: usize,
+    /// Aircraft not seen for longer than that are considered gone
+    expire_after: Duration,
+}
+
+impl Store {
+    pub fn new(
+        history_limit: usize,
+        history_retention: Option<Duration>,
+        track_length: usize,
+        expire_after: Duration,
+    ) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            history: Mutex::default(),
+            history_limit,
+            history_retention,
+            track_length,
+            expire_after,
+        }
+    }
+
+    fn shard(&self, mode_s: &SmolStr) -> &RwLock<HashMap<SmolStr, Aircraft>> {
+        &self.shards[self.hasher.hash_one(mode_s) as usize % SHARDS]
+    }
+
+    /// Stores a new position, updating aircraft state, its track and global history
+    pub fn insert(&self, point: Point) {
+        {
+            let mut shard = self
+                .shard(&point.mode_s)
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            let aircraft = shard
+                .entry(point.mode_s.clone())
+                .or_insert_with(|| Aircraft {
+                    latest: point.clone(),
+                    track: VecDeque::new(),
+                });
+
+            aircraft.track.push_back(TrackPoint::from(&point));
+            while aircraft.track.len() > self.track_length {
+                aircraft.track.pop_front();
+            }
+
+            aircraft.latest = point.clone();
+        }
+
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+
+        history.current.push(point);
+
+        if history.current.len() >= HISTORY_CHUNK {
+            let chunk = std::mem::take(&mut history.current).into();
+            history.sealed.push_back(chunk);
+        }
+
+        self.limit_history(&mut history);
+        self.prune_history(&mut history);
+    }
+
+    /// Leaves out the oldest points beyond the limit. Sealed chunks are shared with
+    /// snapshots, so they're only skipped over until they can be dropped as a whole
+    fn limit_history(&self, history: &mut History) {
+        let mut excess = history.len().saturating_sub(self.history_limit);
+
+        while excess > 0 && !history.sealed.is_empty() {
+            let remaining = HISTORY_CHUNK - history.skipped;
+            if excess < remaining {
+                history.skipped += excess;
+                return;
+            }
+
+            history.pop_chunk();
+            excess -= remaining;
+        }
+
+        // only happens with limits below a single chunk
+        history.current.drain(..excess);
+    }
+
+    /// Drops sealed chunks that are entirely older than retention
+    fn prune_history(&self, history: &mut History) {
+        let Some(cutoff) = self.history_cutoff() else {
+            return;
+        };
+
+        while history
+            .sealed
+            .front()
+            .and_then(|chunk| chunk.last())
+            .is_some_and(|point| point.seen_at < cutoff)
+        {
+            history.pop_chunk();
+        }
+    }
+
+    fn history_cutoff(&self) -> Option<SystemTime> {
+        self.history_retention
+            .and_then(|retention| SystemTime::now().checked_sub(retention))
+    }
+
+    /// Checks whether the point is at exactly the same spot as the latest one of its aircraft,
+    /// e.g. when it's standing on the ground or the decoder repeats itself. If so, only the
+    /// latest point is replaced, keeping the aircraft from expiring, while its track and global
+    /// history are left as is. Aircraft that reappear after expiring never count as repeated
+    pub fn update_if_repeated(&self, point: &Point) -> bool {
+        let mut shard = self
+            .shard(&point.mode_s)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let Some(aircraft) = shard.get_mut(&point.mode_s) else {
+            return false;
+        };
+
+        let latest = &aircraft.latest;
+        let repeated = !self.is_expired(latest)
+            && latest.lat.to_bits() == point.lat.to_bits()
+            && latest.long.to_bits() == point.long.to_bits();
+
+        if repeated {
+            aircraft.latest = point.clone();
+        }
+
+        repeated
+    }
+
+    /// Latest positions of aircraft that aren't expired yet
+    pub fn current_points(&self) -> Vec<Point> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .values()
+                    .filter(|aircraft| !self.is_expired(&aircraft.latest))
+                    .map(|aircraft| aircraft.latest.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Recent path of a single aircraft, oldest first
+    pub fn track(&self, mode_s: &SmolStr) -> Option<Vec<TrackPoint>> {
+        self.shard(mode_s)
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(mode_s)
+            .map(|aircraft| aircraft.track.iter().copied().collect())
+    }
+
+    /// Drops aircraft that weren't seen for longer than `expire_after`, returning their hex idents.
+    /// History beyond retention is pruned as well, as inserts can be rare on a quiet feed
+    pub fn expire(&self) -> Vec<SmolStr> {
+        self.prune_history(&mut self.history.lock().unwrap_or_else(PoisonError::into_inner));
+
+        let mut expired = Vec::new();
+
+        for shard in &self.shards {
+            shard
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|mode_s, aircraft| {
+                    let fresh = !self.is_expired(&aircraft.latest);
+                    if !fresh {
+                        expired.push(mode_s.clone());
+                    }
+
+                    fresh
+                });
+        }
+
+        expired
+    }
+
+    /// All points received recently, oldest first. Only the unsealed chunk is copied
+    pub fn history(&self) -> HistorySnapshot {
+        let history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+
+        HistorySnapshot {
+            sealed: history.sealed.iter().cloned().collect(),
+            current: history.current.clone(),
+            skipped: history.skipped,
+            cutoff: self.history_cutoff(),
+        }
+    }
+
+    /// Aircraft not seen for longer than that are considered gone
+    pub fn expire_after(&self) -> Duration {
+        self.expire_after
+    }
+
+    fn is_expired(&self, point: &Point) -> bool {
+        point.seen_at.elapsed().unwrap_or_default() > self.expire_after
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::point::AircraftInfo;
+
+    use super::*;
+
+    /// Store with that history limit, filled with `count` points numbered by latitude
+    fn filled(history_limit: usize, count: usize) -> Store {
+        let store = Store::new(history_limit, None, 10, Duration::from_secs(60));
+        for i in 0..count {
+            let lat = i as f32 / 1000.0;
+            store.insert(Point::new(
+                "4CA2D1".into(),
+                (lat, 1.0),
+                AircraftInfo::default(),
+            ));
+        }
+
+        store
+    }
+
+    fn history_indices(store: &Store) -> Vec<usize> {
+        store
+            .history()
+            .points()
+            .map(|point| (point.lat * 1000.0).round() as usize)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_everything_within_limit() {
+        let store = filled(40000, 3000);
+        assert_eq!(history_indices(&store), (0..3000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn holds_limits_below_a_chunk() {
+        for limit in [1, 100, HISTORY_CHUNK - 1] {
+            let store = filled(limit, HISTORY_CHUNK * 2 + 10);
+            assert_eq!(
+                history_indices(&store),
+                (HISTORY_CHUNK * 2 + 10 - limit..HISTORY_CHUNK * 2 + 10).collect::<Vec<_>>(),
+                "limit {limit}"
+            );
+        }
+    }
+
+    #[test]
+    fn holds_limits_between_chunks() {
+        for count in [2999, 3000, 3001, HISTORY_CHUNK * 4, HISTORY_CHUNK * 4 + 1] {
+            let store = filled(2500, count);
+            assert_eq!(
+                history_indices(&store),
+                (count - 2500..count).collect::<Vec<_>>(),
+                "{count} points"
+            );
+        }
+    }
+
+    #[test]
+    fn drops_chunks_past_the_limit() {
+        let store = filled(1500, HISTORY_CHUNK * 10);
+        let history = store.history.lock().unwrap();
+
+        assert!(history.sealed.len() <= 2);
+        assert_eq!(history.len(), 1500);
+    }
+}